@@ -0,0 +1,654 @@
+// Anki packages (.apkg).
+//
+// A package is a zip archive with the collection database inside. Only the
+// legacy schema (`collection.anki2` and `collection.anki21`) is supported,
+// newer versions of Anki write it when "Support older Anki versions" is
+// checked on export.
+//
+// Notes of standard note types (Basic, Basic and reversed card, etc.) are
+// converted into cards with a side for each field, notes with a single field
+// into text notes. Cloze notes are skipped. Anki fields are HTML, it is
+// converted to plain text.
+//
+// Exported packages have two note types: "fhmp Card" with front and back
+// fields, where extra sides of a card are joined into the back, and
+// "fhmp Text" with a single field. All cards are put into the default deck
+// as new cards, the package can be imported into any deck in Anki.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::{env, fs, io, path::{Path, PathBuf}};
+use std::sync::OnceLock;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{Local, TimeZone, Utc};
+use rusqlite::params;
+use regex::Regex;
+use rusqlite::Connection;
+use serde::Deserialize;
+use uuid::Uuid;
+use crate::config::CliConfig;
+use crate::db::{self, Inserted};
+use crate::note::{DbNote, InputNote, NoteData};
+use crate::sync::reschedule;
+
+// Namespace for UUIDs of notes imported from Anki, derived from note guids.
+const ANKI_NAMESPACE: Uuid = Uuid::from_u128(0x8d3c_51a0_7e2b_4f96_a4d8_0b6e_c913_52f7);
+
+// Fields of a note are separated by the unit separator.
+const FIELD_SEPARATOR: char = '\x1f';
+
+// The deck that always exists in Anki collections. Its name is not used as
+// a tag on import.
+const DEFAULT_DECK_ID: i64 = 1;
+
+// Ids of note types in exported packages. Anki matches note types by id on
+// import, so they must stay the same between exports.
+const CARD_MODEL_ID: i64 = 1_700_000_000_001;
+const TEXT_MODEL_ID: i64 = 1_700_000_000_002;
+
+// Schema of the legacy collection, version 11.
+const COLLECTION_SCHEMA: &str = "
+    create table col (id integer primary key, crt integer not null,
+        mod integer not null, scm integer not null, ver integer not null,
+        dty integer not null, usn integer not null, ls integer not null,
+        conf text not null, models text not null, decks text not null,
+        dconf text not null, tags text not null);
+    create table notes (id integer primary key, guid text not null,
+        mid integer not null, mod integer not null, usn integer not null,
+        tags text not null, flds text not null, sfld integer not null,
+        csum integer not null, flags integer not null, data text not null);
+    create table cards (id integer primary key, nid integer not null,
+        did integer not null, ord integer not null, mod integer not null,
+        usn integer not null, type integer not null, queue integer not null,
+        due integer not null, ivl integer not null, factor integer not null,
+        reps integer not null, lapses integer not null, left integer not null,
+        odue integer not null, odid integer not null, flags integer not null,
+        data text not null);
+    create table revlog (id integer primary key, cid integer not null,
+        usn integer not null, ease integer not null, ivl integer not null,
+        lastIvl integer not null, factor integer not null, time integer not null,
+        type integer not null);
+    create table graves (usn integer not null, oid integer not null,
+        type integer not null);
+    create index ix_notes_usn on notes (usn);
+    create index ix_cards_usn on cards (usn);
+    create index ix_revlog_usn on revlog (usn);
+    create index ix_cards_nid on cards (nid);
+    create index ix_cards_sched on cards (did, queue, due);
+    create index ix_revlog_cid on revlog (cid);
+    create index ix_notes_csum on notes (csum);
+";
+
+#[derive(Deserialize)]
+struct Model {
+    name: String,
+    // 0 for standard note types, 1 for cloze.
+    #[serde(rename = "type")]
+    kind: i64,
+    flds: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct Deck {
+    name: String,
+}
+
+#[derive(Default)]
+pub struct ImportStats {
+    pub notes: usize,
+    pub reviews: usize,
+    // Number of skipped notes by note type.
+    pub skipped: BTreeMap<String, usize>,
+}
+
+// Removes the extracted collection when the import is done.
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _res = fs::remove_file(&self.0);
+    }
+}
+
+// Imports notes from the package, and also their reviews if `with_reviews`
+// is set. Importing the same package again changes nothing.
+pub fn import(
+    db: &Connection,
+    cfg: &CliConfig,
+    path: &Path,
+    with_reviews: bool
+) -> Result<ImportStats> {
+    let file = extract_collection(path)?;
+    let col = Connection::open(&file.0)
+        .context("Opening Anki collection")?;
+
+    let tx = db.unchecked_transaction()?;
+    let mut stats = ImportStats::default();
+    let uuids = import_notes(&tx, &col, &mut stats)?;
+    if with_reviews {
+        stats.reviews = import_reviews(&tx, cfg, &col, &uuids)?;
+    }
+    tx.commit()?;
+    Ok(stats)
+}
+
+fn extract_collection(path: &Path) -> Result<TempFile> {
+    let mut zip = zip::ZipArchive::new(fs::File::open(path)?)
+        .context("Reading Anki package")?;
+    let hint = "export it with \"Support older Anki versions\" checked";
+    // Packages of the new format have a stub `collection.anki2` as well.
+    if zip.by_name("collection.anki21b").is_ok() {
+        bail!("The package has a collection of an unsupported version, {}", hint);
+    }
+    // `collection.anki2` is a stub in packages that have `collection.anki21`.
+    let name = ["collection.anki21", "collection.anki2"]
+        .into_iter()
+        .find(|name| zip.by_name(name).is_ok())
+        .ok_or_else(|| anyhow!("No collection in the package, {}", hint))?;
+
+    let file = TempFile(env::temp_dir().join(format!("fhmp-anki-{}.sqlite", Uuid::new_v4())));
+    io::copy(&mut zip.by_name(name)?, &mut fs::File::create(&file.0)?)?;
+    Ok(file)
+}
+
+// Returns UUIDs of imported notes by Anki note ids.
+fn import_notes(
+    db: &Connection,
+    col: &Connection,
+    stats: &mut ImportStats
+) -> Result<HashMap<i64, Uuid>> {
+    let (models, decks): (String, String) = col.query_row(
+        "select models, decks from col",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)))?;
+    let models: HashMap<String, Model> = serde_json::from_str(&models)?;
+    let decks: HashMap<String, Deck> = serde_json::from_str(&decks)?;
+
+    let mut q = col.prepare("
+        select n.id, n.guid, n.mid, n.tags, n.flds, group_concat(c.did)
+        from notes n
+        left join cards c on c.nid = n.id
+        group by n.id
+        order by n.id
+    ")?;
+    let mut rows = q.query([])?;
+    let mut uuids = HashMap::new();
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let guid: String = row.get(1)?;
+        let mid: i64 = row.get(2)?;
+        let model = match models.get(&mid.to_string()) {
+            Some(m) if m.kind == 0 && !m.flds.is_empty() => m,
+            m => {
+                let name = m.map_or("unknown", |m| m.name.as_str());
+                *stats.skipped.entry(name.to_string()).or_default() += 1;
+                continue;
+            }
+        };
+
+        // Anki uses `::` to separate levels of hierarchical tags and decks.
+        let note_tags: String = row.get(3)?;
+        let deck_ids: Option<String> = row.get(5)?;
+        let deck_names = deck_ids.iter()
+            .flat_map(|ids| ids.split(','))
+            .filter(|did| *did != DEFAULT_DECK_ID.to_string())
+            .filter_map(|did| decks.get(did))
+            .map(|d| d.name.as_str());
+        let tags: BTreeSet<_> = note_tags.split_whitespace()
+            .chain(deck_names)
+            .map(|t| t.replace("::", "/"))
+            .collect();
+
+        let flds: String = row.get(4)?;
+        let mut sides: Vec<String> = flds.split(FIELD_SEPARATOR)
+            .take(model.flds.len())
+            .map(html_to_text)
+            .collect();
+        while sides.len() > 2 && sides.last().is_some_and(String::is_empty) {
+            sides.pop();
+        }
+
+        let data = match sides.len() {
+            1 => NoteData::Text(sides.remove(0)),
+            _ => NoteData::Card(sides),
+        };
+
+        let uuid = Uuid::parse_str(&guid)
+            .unwrap_or_else(|_| Uuid::new_v5(&ANKI_NAMESPACE, guid.as_bytes()));
+        let note = InputNote {
+            uuid: Some(uuid),
+            // Note ids are creation times in milliseconds.
+            ctime: Local.timestamp_millis_opt(id).single(),
+            tags: tags.into_iter().collect::<Vec<_>>().join(","),
+            data,
+            parent: None,
+        }.to_db_note()?;
+        if db::insert_note(db, &note)? != Inserted::Exists {
+            stats.notes += 1;
+        }
+        uuids.insert(id, uuid);
+    }
+    Ok(uuids)
+}
+
+// Returns the number of new reviews.
+fn import_reviews(
+    db: &Connection,
+    cfg: &CliConfig,
+    col: &Connection,
+    uuids: &HashMap<i64, Uuid>
+) -> Result<usize> {
+    // Ease is 0 for manual rescheduling.
+    let mut q = col.prepare("
+        select r.id, c.nid, r.ease, r.ivl, r.factor, r.time
+        from revlog r, cards c
+        where c.id = r.cid
+          and r.ease between 1 and 4
+        order by r.id
+    ")?;
+    let mut rows = q.query([])?;
+    let mut affected = BTreeSet::new();
+    let mut n = 0;
+    while let Some(row) = rows.next()? {
+        let Some(uuid) = uuids.get(&row.get::<_, i64>(1)?) else {
+            continue;
+        };
+        let ctime = Utc.timestamp_millis_opt(row.get(0)?).single()
+            .ok_or_else(|| anyhow!("Invalid review time"))?;
+        let result = ["again", "hard", "good", "easy"][row.get::<_, usize>(2)? - 1];
+        let decision = serde_json::json!({
+            "source": "anki",
+            "ivl": row.get::<_, i64>(3)?,
+            "factor": row.get::<_, i64>(4)?,
+            "duration": row.get::<_, i64>(5)? / 1000,
+        });
+        if db::insert_review(db, uuid, &ctime, result, &decision)? {
+            affected.insert(*uuid);
+            n += 1;
+        }
+    }
+
+    for uuid in affected.iter() {
+        reschedule(db, cfg, uuid)?;
+    }
+    Ok(n)
+}
+
+fn html_to_text(html: &str) -> String {
+    // Called for every field, the regexes are compiled once.
+    static LINE_BREAKS: OnceLock<Regex> = OnceLock::new();
+    static TAGS: OnceLock<Regex> = OnceLock::new();
+    let line_breaks = LINE_BREAKS
+        .get_or_init(|| Regex::new(r"(?i)<br\s*/?>|<div>|</p>").expect("valid regex"));
+    let tags = TAGS.get_or_init(|| Regex::new(r"<[^>]*>").expect("valid regex"));
+    let text = line_breaks.replace_all(html, "\n");
+    let text = tags.replace_all(&text, "");
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+// Writes the notes into a new package, returns the number of exported notes.
+pub fn export(notes: &[DbNote], path: &Path) -> Result<usize> {
+    let file = TempFile(env::temp_dir().join(format!("fhmp-anki-{}.sqlite", Uuid::new_v4())));
+    let col = Connection::open(&file.0)
+        .context("Creating Anki collection")?;
+    write_collection(&col, notes)?;
+    drop(col);
+
+    let mut zip = zip::ZipWriter::new(fs::File::create(path)?);
+    let options = zip::write::FileOptions::default();
+    zip.start_file("collection.anki2", options)?;
+    io::copy(&mut fs::File::open(&file.0)?, &mut zip)?;
+    // Exported notes have no media files.
+    zip.start_file("media", options)?;
+    io::Write::write_all(&mut zip, b"{}")?;
+    zip.finish()?;
+    Ok(notes.len())
+}
+
+fn write_collection(col: &Connection, notes: &[DbNote]) -> Result<()> {
+    let now = Utc::now();
+    let tx = col.unchecked_transaction()?;
+    tx.execute_batch(COLLECTION_SCHEMA)?;
+    tx.execute("
+        insert into col values (1, ?1, ?2, ?3, 11, 0, 0, 0, ?4, ?5, ?6, ?7, '{}')",
+        params![
+            now.timestamp(),
+            now.timestamp(),
+            now.timestamp_millis(),
+            collection_conf(notes.len()).to_string(),
+            serde_json::json!({
+                CARD_MODEL_ID.to_string(): note_type(CARD_MODEL_ID, "fhmp Card", &["Front", "Back"]),
+                TEXT_MODEL_ID.to_string(): note_type(TEXT_MODEL_ID, "fhmp Text", &["Text"]),
+            }).to_string(),
+            serde_json::json!({"1": default_deck()}).to_string(),
+            serde_json::json!({"1": default_deck_conf()}).to_string(),
+        ])?;
+
+    // Note ids are creation times in milliseconds and must be unique.
+    let mut last_id = 0;
+    for (pos, note) in notes.iter().enumerate() {
+        let id = note.ctime.timestamp_millis().max(last_id + 1);
+        last_id = id;
+        let (mid, fields) = match &note.data {
+            NoteData::Text(text) => (TEXT_MODEL_ID, vec![text_to_html(text)]),
+            NoteData::Card(sides) => {
+                let front = sides.first().map_or("", String::as_str);
+                let back = sides.iter().skip(1)
+                    .map(|s| text_to_html(s))
+                    .collect::<Vec<_>>()
+                    .join("<br><br>");
+                (CARD_MODEL_ID, vec![text_to_html(front), back])
+            }
+        };
+        // Anki uses the checksum of the first field to find duplicates.
+        let sort_field = html_to_text(&fields[0]);
+        let digest = sha1_smol::Sha1::from(&sort_field).digest().bytes();
+        let csum = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+        let tags: Vec<_> = note.tags.split_whitespace()
+            .map(|t| t.replace('/', "::"))
+            .collect();
+
+        tx.execute("
+            insert into notes values (?1, ?2, ?3, ?4, -1, ?5, ?6, ?7, ?8, 0, '')",
+            params![
+                id,
+                note.uuid.to_string(),
+                mid,
+                now.timestamp(),
+                format!(" {} ", tags.join(" ")),
+                fields.join(&FIELD_SEPARATOR.to_string()),
+                sort_field,
+                csum,
+            ])?;
+        // A new card in the default deck.
+        tx.execute("
+            insert into cards
+            values (?1, ?1, ?2, 0, ?3, -1, 0, 0, ?4, 0, 0, 0, 0, 0, 0, 0, 0, '')",
+            params![id, DEFAULT_DECK_ID, now.timestamp(), pos + 1])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn collection_conf(next_pos: usize) -> serde_json::Value {
+    serde_json::json!({
+        "nextPos": next_pos + 1,
+        "estTimes": true,
+        "activeDecks": [DEFAULT_DECK_ID],
+        "sortType": "noteFld",
+        "timeLim": 0,
+        "sortBackwards": false,
+        "addToCur": true,
+        "curDeck": DEFAULT_DECK_ID,
+        "newBury": true,
+        "newSpread": 0,
+        "dueCounts": true,
+        "curModel": CARD_MODEL_ID.to_string(),
+        "collapseTime": 1200,
+    })
+}
+
+fn note_type(id: i64, name: &str, fields: &[&str]) -> serde_json::Value {
+    let flds: Vec<_> = fields.iter().enumerate()
+        .map(|(ord, name)| serde_json::json!({
+            "name": name,
+            "ord": ord,
+            "sticky": false,
+            "rtl": false,
+            "font": "Arial",
+            "size": 20,
+            "media": [],
+        }))
+        .collect();
+    let (qfmt, afmt) = match fields {
+        [front, back, ..] => (
+            format!("{{{{{}}}}}", front),
+            format!("{{{{FrontSide}}}}\n\n<hr id=answer>\n\n{{{{{}}}}}", back),
+        ),
+        _ => (
+            format!("{{{{{}}}}}", fields[0]),
+            format!("{{{{{}}}}}", fields[0]),
+        ),
+    };
+    serde_json::json!({
+        "id": id,
+        "name": name,
+        "type": 0,
+        "mod": Utc::now().timestamp(),
+        "usn": -1,
+        "sortf": 0,
+        "did": DEFAULT_DECK_ID,
+        "tmpls": [{
+            "name": "Card 1",
+            "ord": 0,
+            "qfmt": qfmt,
+            "afmt": afmt,
+            "did": null,
+            "bqfmt": "",
+            "bafmt": "",
+        }],
+        "flds": flds,
+        "css": ".card {\n font-family: arial;\n font-size: 20px;\n text-align: center;\n}\n",
+        "latexPre": "\\documentclass[12pt]{article}\n\\special{papersize=3in,5in}\n\\usepackage[utf8]{inputenc}\n\\usepackage{amssymb,amsmath}\n\\pagestyle{empty}\n\\setlength{\\parindent}{0in}\n\\begin{document}\n",
+        "latexPost": "\\end{document}",
+        "tags": [],
+        "vers": [],
+        "req": [[0, "any", [0]]],
+    })
+}
+
+fn default_deck() -> serde_json::Value {
+    serde_json::json!({
+        "id": DEFAULT_DECK_ID,
+        "name": "Default",
+        "mod": Utc::now().timestamp(),
+        "usn": -1,
+        "lrnToday": [0, 0],
+        "revToday": [0, 0],
+        "newToday": [0, 0],
+        "timeToday": [0, 0],
+        "collapsed": false,
+        "desc": "",
+        "dyn": 0,
+        "conf": 1,
+        "extendNew": 10,
+        "extendRev": 50,
+    })
+}
+
+fn default_deck_conf() -> serde_json::Value {
+    serde_json::json!({
+        "id": 1,
+        "name": "Default",
+        "mod": 0,
+        "usn": 0,
+        "maxTaken": 60,
+        "autoplay": true,
+        "timer": 0,
+        "replayq": true,
+        "dyn": false,
+        "new": {
+            "delays": [1, 10],
+            "ints": [1, 4, 7],
+            "initialFactor": 2500,
+            "order": 1,
+            "perDay": 20,
+            "bury": true,
+            "separate": true,
+        },
+        "rev": {
+            "perDay": 100,
+            "ease4": 1.3,
+            "fuzz": 0.05,
+            "ivlFct": 1,
+            "maxIvl": 36500,
+            "bury": true,
+            "minSpace": 1,
+        },
+        "lapse": {
+            "delays": [10],
+            "mult": 0,
+            "minInt": 1,
+            "leechFails": 8,
+            "leechAction": 0,
+        },
+    })
+}
+
+fn text_to_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\n', "<br>")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn test_config() -> CliConfig {
+        config::Config::builder()
+            .add_source(config::File::from_str(
+                "db_path = \"\"\ndata_path = \"\"",
+                config::FileFormat::Toml))
+            .build()
+            .and_then(|c| c.try_deserialize())
+            .expect("valid config")
+    }
+
+    // Package with the part of the Anki schema used by the importer, and
+    // with the `extra` files.
+    fn test_package(extra: &[(&str, &[u8])]) -> Result<TempFile> {
+        let col_file = TempFile(env::temp_dir().join(format!("fhmp-test-{}.anki2", Uuid::new_v4())));
+        let col = Connection::open(&col_file.0)?;
+        col.execute_batch(r#"
+            create table col (models text, decks text);
+            create table notes (id integer primary key, guid text, mid integer,
+                tags text, flds text);
+            create table cards (id integer primary key, nid integer, did integer);
+            create table revlog (id integer primary key, cid integer, ease integer,
+                ivl integer, factor integer, time integer);
+            insert into col values (
+                '{"1": {"name": "Basic", "type": 0, "flds": [{}, {}]},
+                  "2": {"name": "Cloze", "type": 1, "flds": [{}, {}]}}',
+                '{"1": {"name": "Default"}, "7": {"name": "Lang::Spanish"}}');
+            insert into notes values
+                (1700000000000, 'abc', 1, ' verbs Lang::ES ',
+                    'to eat<br>(irregular)' || char(31) || 'comer&nbsp;&amp; <b>more</b>'),
+                (1700000000001, 'def', 2, '', '{{c1::cloze}}' || char(31) || '');
+            insert into cards values (10, 1700000000000, 7), (11, 1700000000000, 7),
+                (12, 1700000000001, 1);
+            insert into revlog values
+                (1700000100000, 10, 3, 1, 2500, 8000),
+                (1700000200000, 11, 1, 0, 2500, 3000),
+                (1700000300000, 12, 4, 4, 2500, 1000);
+        "#)?;
+        drop(col);
+
+        let pkg = TempFile(env::temp_dir().join(format!("fhmp-test-{}.apkg", Uuid::new_v4())));
+        let mut zip = zip::ZipWriter::new(fs::File::create(&pkg.0)?);
+        zip.start_file("collection.anki2", zip::write::FileOptions::default())?;
+        zip.write_all(&fs::read(&col_file.0)?)?;
+        zip.start_file("media", zip::write::FileOptions::default())?;
+        zip.write_all(b"{}")?;
+        for (name, data) in extra {
+            zip.start_file(*name, zip::write::FileOptions::default())?;
+            zip.write_all(data)?;
+        }
+        zip.finish()?;
+        Ok(pkg)
+    }
+
+    #[test]
+    fn import_basic_notes() -> Result<()> {
+        let db = db::open(":memory:")?;
+        db::init_schema(&db)?;
+        let cfg = test_config();
+        let pkg = test_package(&[])?;
+
+        let stats = import(&db, &cfg, &pkg.0, true)?;
+        assert_eq!((stats.notes, stats.reviews), (1, 2));
+        assert_eq!(stats.skipped.get("Cloze"), Some(&1));
+
+        let note = db::active_notes(&db)?.iter()?.next().unwrap()?;
+        assert_eq!(note.uuid, Uuid::new_v5(&ANKI_NAMESPACE, b"abc"));
+        assert_eq!(note.tags, "lang/es\nlang/spanish\nverbs");
+        assert_eq!(note.data, NoteData::Card(vec![
+            "to eat\n(irregular)".to_string(),
+            "comer & more".to_string(),
+        ]));
+        assert_eq!(note.ctime.timestamp_millis(), 1700000000000);
+        let history = db::review_history(&db, &note.uuid)?;
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].result.label(), "again");
+
+        // Nothing changes on the second import.
+        let stats = import(&db, &cfg, &pkg.0, true)?;
+        assert_eq!((stats.notes, stats.reviews), (0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn new_format_is_rejected() -> Result<()> {
+        let db = db::open(":memory:")?;
+        db::init_schema(&db)?;
+        // `collection.anki2` is a valid collection, but only a stub.
+        let pkg = test_package(&[("collection.anki21b", b"zstd")])?;
+
+        let err = import(&db, &test_config(), &pkg.0, false)
+            .err().expect("anki21b is not supported").to_string();
+        assert!(err.contains("Support older Anki versions"), "{}", err);
+        assert_eq!(db::active_notes(&db)?.iter()?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn export_round_trip() -> Result<()> {
+        let db = db::open(":memory:")?;
+        db::init_schema(&db)?;
+        let cfg = test_config();
+        let notes = vec![
+            InputNote {
+                uuid: Some(Uuid::new_v4()),
+                ctime: Local.timestamp_millis_opt(1700000000000).single(),
+                tags: "lang/es,verbs".to_string(),
+                data: NoteData::Card(vec![
+                    "to eat\n(irregular)".to_string(),
+                    "comer & <more>".to_string(),
+                ]),
+                parent: None,
+            }.to_db_note()?,
+            InputNote {
+                uuid: Some(Uuid::new_v4()),
+                ctime: Local.timestamp_millis_opt(1700000000000).single(),
+                tags: "quotes".to_string(),
+                data: NoteData::Text("\"Premature optimization\"".to_string()),
+                parent: None,
+            }.to_db_note()?,
+        ];
+        let pkg = TempFile(env::temp_dir().join(format!("fhmp-test-{}.apkg", Uuid::new_v4())));
+        assert_eq!(export(&notes, &pkg.0)?, 2);
+
+        let stats = import(&db, &cfg, &pkg.0, true)?;
+        assert_eq!((stats.notes, stats.reviews), (2, 0));
+        assert!(stats.skipped.is_empty());
+        let imported = db::active_notes(&db)?.iter()?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(imported.len(), 2);
+        for (note, orig) in imported.iter().zip(notes.iter()) {
+            assert_eq!(note.uuid, orig.uuid);
+            assert_eq!(note.tags, orig.tags);
+            assert_eq!(note.data, orig.data);
+        }
+        // Notes created at the same time get distinct ids.
+        assert_eq!(imported[1].ctime.timestamp_millis(), 1700000000001);
+        Ok(())
+    }
+}
@@ -1,38 +1,76 @@
-use std::io;
 use anyhow::{anyhow, Context, Result};
-use serde::Deserialize;
 
 use crate::config::read_config;
-use crate::note::{InputNote, DbNote};
-use crate::db::{init_schema, insert_notes};
+use crate::note::{diff, InputNote, DbNote, NoteData};
+use crate::db::{self, init_schema, insert_notes};
+use crate::input;
+use crate::plan::{self, Change};
+use crate::table::TableFormat;
 
-pub fn exec() -> Result<()> {
+// Notes are read from stdin if no paths are given.
+// With `--dry-run` the changes are printed and nothing is written, it may be
+// given before or after other options and paths.
+pub fn exec(args: &[String]) -> Result<()> {
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let args: Vec<String> = args.iter()
+        .filter(|a| *a != "--dry-run")
+        .cloned()
+        .collect();
+    let (fmt, paths) = TableFormat::from_args(&args)?;
     let cfg = read_config()
         .context("Reading config")?;
 
-    let db = sqlite::open(&cfg.db_path)
+    let db = db::open(&cfg.db_path)
         .context("Opening database file")?;
     init_schema(&db)
         .context("Initializing database schema")?;
-    let notes = read_notes(io::stdin())
-        .context("Reading notes from stdin")?;
+    let notes = if paths.is_empty() {
+        input::read_stdin(&fmt)
+            .context("Reading notes from stdin")?
+    } else {
+        input::read_paths(paths, &fmt)
+            .context("Reading notes from files")?
+    };
     let notes = transform_notes(&notes)
         .context("Invalid note format")?;
-    insert_notes(&db, &notes)
+    if dry_run {
+        return print_plan(&db, &notes);
+    }
+    let conflicts = insert_notes(&db, &notes)?;
+    if conflicts > 0 {
+        println!("{} notes were changed since they were dumped and are saved as \
+            conflicting versions, see `fhmp conflicts`.", conflicts);
+    }
+    Ok(())
+}
+
+fn print_plan(db: &rusqlite::Connection, notes: &[DbNote]) -> Result<()> {
+    let changes = plan::plan(db, notes)?;
+    for (n, change) in notes.iter().zip(changes.iter()) {
+        println!("{:<12}{}  {}", change.label(), n.uuid, summary(n));
+        if let Change::Updated(current) | Change::Conflict(current) = change {
+            for line in diff(current, n, "current", "new").lines() {
+                println!("    {}", line);
+            }
+        }
+    }
+    let counts: Vec<_> = ["new", "unchanged", "updated", "resurrected", "conflict", "deleted"].iter()
+        .map(|l| format!("{} {}", changes.iter().filter(|c| c.label() == *l).count(), l))
+        .collect();
+    println!("\n{}. Nothing was written.", counts.join(", "));
+    Ok(())
 }
 
-fn read_notes<T: io::Read>(r: T) -> Result<Vec<InputNote>> {
-    let val = serde_yaml::from_reader(r)?;
-    match val {
-        serde_yaml::Value::Sequence(_) =>
-            Vec::<InputNote>::deserialize(val)
-                .context("Parsing a sequence of notes from YAML"),
-        serde_yaml::Value::Mapping(_) => Ok(vec![
-            InputNote::deserialize(val)
-                .context("Parsing a note from YAML")?
-
-        ]),
-        _ => anyhow::bail!("Object or sequence of objects expected.")
+// The first line of the note, shortened.
+fn summary(note: &DbNote) -> String {
+    let text = match &note.data {
+        NoteData::Text(text) => text.as_str(),
+        NoteData::Card(sides) => sides[0].as_str(),
+    };
+    let line = text.lines().next().unwrap_or("");
+    match line.char_indices().nth(60) {
+        Some((i, _)) => format!("{}…", &line[..i]),
+        None => line.to_string(),
     }
 }
 
@@ -0,0 +1,100 @@
+use std::{env, fs};
+use anyhow::{Context, Result};
+use dialoguer::{theme::ColorfulTheme, FuzzySelect};
+use rusqlite::Connection;
+use uuid::Uuid;
+
+use crate::cmd_new::run_editor;
+use crate::config::read_config;
+use crate::db::{self, conflicting_notes, current_note, init_schema, insert_notes, resolve_conflict};
+use crate::note::{DbNote, InputNote};
+
+// Lists notes that were edited concurrently and asks which version to keep.
+//
+// Choosing one of the existing versions is a local decision, other databases
+// keep their conflicts. A merged version made with "Edit" is based on the
+// conflicting versions and resolves the conflict everywhere after sync.
+pub fn exec() -> Result<()> {
+    let cfg = read_config()
+        .context("Reading config")?;
+    let db = db::open(&cfg.db_path)
+        .context("Opening database file")?;
+    init_schema(&db)
+        .context("Initializing database schema")?;
+
+    let conflicts = conflicting_notes(&db)?;
+    if conflicts.is_empty() {
+        println!("No conflicts.");
+        return Ok(());
+    }
+
+    // Conflicting versions are ordered by note.
+    let mut versions: Vec<DbNote> = Vec::new();
+    for note in conflicts {
+        if versions.first().is_some_and(|v| v.uuid != note.uuid) {
+            resolve(&db, &versions)?;
+            versions.clear();
+        }
+        if versions.is_empty() {
+            versions.extend(current_note(&db, &note.uuid)?);
+        }
+        versions.push(note);
+    }
+    resolve(&db, &versions)
+}
+
+// The first version is the current one, if any.
+fn resolve(db: &Connection, versions: &[DbNote]) -> Result<()> {
+    let uuid = versions[0].uuid;
+    println!("\nNote {} has {} versions:", uuid, versions.len());
+    for (i, v) in versions.iter().enumerate() {
+        println!("\n--- version {} ---", i + 1);
+        print!("{}", serde_yaml::to_string(v)?);
+    }
+
+    let theme = ColorfulTheme::default();
+    let items: Vec<_> = (1..=versions.len())
+        .map(|i| format!("Keep version {}", i))
+        .chain(["Edit".to_string(), "Skip".to_string()])
+        .collect();
+    let res = FuzzySelect::with_theme(&theme)
+        .default(0)
+        .items(&items)
+        .interact()?;
+
+    if let Some(v) = versions.get(res) {
+        let (hash, _) = v.hash_and_json();
+        resolve_conflict(db, &uuid, &hash)
+    } else if res == versions.len() {
+        let merged = edit_versions(&uuid, versions)?;
+        insert_notes(db, std::slice::from_ref(&merged))?;
+        let (hash, _) = merged.hash_and_json();
+        resolve_conflict(db, &uuid, &hash)
+    } else {
+        Ok(())
+    }
+}
+
+// Opens the first version in the editor with other versions as comments.
+fn edit_versions(uuid: &Uuid, versions: &[DbNote]) -> Result<DbNote> {
+    let file = env::temp_dir().join(format!("fhmp-conflict-{}.yaml", uuid));
+    let mut text = serde_yaml::to_string(&versions[0])?;
+    for (i, v) in versions.iter().enumerate().skip(1) {
+        text.push_str(&format!("\n# --- version {} ---\n", i + 1));
+        for line in serde_yaml::to_string(v)?.lines() {
+            text.push_str(&format!("# {}\n", line));
+        }
+    }
+    fs::write(&file, text)?;
+    run_editor(&file)?;
+
+    let input: InputNote = serde_yaml::from_reader(fs::File::open(&file)?)
+        .context("Parsing the edited note")?;
+    fs::remove_file(&file)?;
+    let (base, _) = versions[0].hash_and_json();
+    Ok(DbNote {
+        uuid: *uuid,
+        parent: Some(base),
+        ..input.to_db_note()?
+    })
+}
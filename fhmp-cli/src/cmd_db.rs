@@ -0,0 +1,29 @@
+use anyhow::{Context, Result};
+use crate::config::read_config;
+use crate::db;
+use crate::migrations;
+
+pub fn exec_migrate(dry_run: bool) -> Result<()> {
+    let cfg = read_config()
+        .context("Reading config")?;
+    let db = db::open(&cfg.db_path)
+        .context("Opening database file")?;
+
+    let pending = migrations::pending(&db)?;
+    if pending.is_empty() {
+        println!("Database schema is up to date (version {}).",
+            migrations::latest_version());
+        return Ok(());
+    }
+
+    for (version, m) in pending.iter() {
+        println!("{:>4} {}", version, m.description);
+    }
+    if !dry_run {
+        migrations::migrate(&db)
+            .context("Migrating database schema")?;
+        println!("Database schema is migrated to version {}.",
+            migrations::latest_version());
+    }
+    Ok(())
+}
@@ -2,18 +2,35 @@ use anyhow::{anyhow, Context, Result};
 use serde::ser::Serializer;
 use serde::ser::SerializeSeq;
 use crate::config::read_config;
-use crate::db::active_notes;
+use crate::db::{self, init_schema, matching_notes};
+use crate::markdown;
+use crate::query;
+use crate::table::{self, Format, TableFormat};
 
-pub fn exec() -> Result<()> {
+pub fn exec(args: &[String]) -> Result<()> {
+    let (fmt, args) = TableFormat::from_args(args)?;
+    let query = query::from_args(args)
+        .context("Invalid query")?;
     let cfg = read_config()
         .context("Reading config")?;
-    let db = sqlite::open(&cfg.db_path)
+    let db = db::open(&cfg.db_path)
         .context("Opening database file")?;
+    init_schema(&db)
+        .context("Initializing database schema")?;
+
+    // Edited notes are added as new versions of the dumped ones.
+    let mut notes = matching_notes(&db, query.as_ref())?.with_parents();
+    let notes = notes.iter()?;
+    match fmt.format {
+        Format::Yaml => {}
+        Format::Markdown => return markdown::write_notes(std::io::stdout(), notes),
+        Format::Csv | Format::Tsv => return table::write_notes(std::io::stdout(), &fmt, notes),
+    }
 
     let mut s = serde_yaml::Serializer::new(std::io::stdout());
     let mut ss = s.serialize_seq(None)?;
-    for n in active_notes(&db)? {
-        ss.serialize_element(&n)?;
+    for n in notes {
+        ss.serialize_element(&n?)?;
     }
     ss.end().map_err(|e| anyhow!(e))
 }
@@ -0,0 +1,266 @@
+use std::{env, fs, path::Path};
+use anyhow::{bail, Context, Result};
+use uuid::Uuid;
+
+use crate::cmd_new::run_editor;
+use crate::config::read_config;
+use crate::db::{self, init_schema, insert_note, matching_notes, notes_by_uuid_prefix, Inserted};
+use crate::input::read_yaml_notes;
+use crate::note::DbNote;
+use crate::query;
+
+const HEADER: &str = "\
+# Edit the notes and save the file, delete everything to cancel.
+";
+
+// Lines with errors of the previous attempt start with this prefix.
+const ERROR_PREFIX: &str = "#!";
+
+// Edits notes with the UUID prefix, or matching the query, in $EDITOR.
+// The editor is opened again until the notes are valid.
+pub fn exec(args: &[String]) -> Result<()> {
+    let cfg = read_config()
+        .context("Reading config")?;
+    let db = db::open(&cfg.db_path)
+        .context("Opening database file")?;
+    init_schema(&db)
+        .context("Initializing database schema")?;
+
+    let notes = find_notes(&db, args)?;
+    if notes.is_empty() {
+        println!("No matching notes.");
+        return Ok(());
+    }
+
+    // Edited notes are new versions of the current ones.
+    let notes = notes.into_iter()
+        .map(|n| Ok(DbNote { parent: db::current_hash(&db, &n.uuid)?, ..n }))
+        .collect::<Result<Vec<_>>>()?;
+    let file = env::temp_dir().join(format!("fhmp-edit-{}.yaml", Uuid::new_v4()));
+    fs::write(&file, notes_to_yaml(notes)?)?;
+    let res = edit_until_valid(&file);
+    fs::remove_file(&file)?;
+    let Some(edited) = res? else {
+        println!("Cancelled.");
+        return Ok(());
+    };
+
+    let (mut updated, mut conflicts) = (0, 0);
+    let tx = db.unchecked_transaction()?;
+    for n in edited.iter() {
+        match insert_note(&tx, n)? {
+            Inserted::Exists => {},
+            Inserted::New => updated += 1,
+            Inserted::Conflict => conflicts += 1,
+        }
+    }
+    tx.commit()?;
+    println!("Saved {} notes.", updated);
+    if conflicts > 0 {
+        println!("{} notes were changed while being edited and are saved as \
+            conflicting versions, see `fhmp conflicts`.", conflicts);
+    }
+    Ok(())
+}
+
+// Tags are written comma separated, the way they are read back, so that
+// notes left as they are keep their hashes.
+fn notes_to_yaml(notes: Vec<DbNote>) -> Result<String> {
+    let notes: Vec<_> = notes.into_iter()
+        .map(|n| DbNote { tags: n.tags.replace('\n', ", "), ..n })
+        .collect();
+    Ok(format!("{}{}", HEADER, serde_yaml::to_string(&notes)?))
+}
+
+// A single argument is a UUID prefix if there are notes with it.
+fn find_notes(db: &rusqlite::Connection, args: &[String]) -> Result<Vec<DbNote>> {
+    if let [arg] = args {
+        let is_prefix = arg.len() >= 4
+            && arg.chars().all(|c| c.is_ascii_hexdigit() || c == '-');
+        if is_prefix {
+            let notes = notes_by_uuid_prefix(db, arg)?;
+            if !notes.is_empty() {
+                return Ok(notes);
+            }
+        }
+    }
+    if args.is_empty() {
+        bail!("UUID prefix or query expected");
+    }
+    let query = query::from_args(args)
+        .context("Invalid query")?;
+    matching_notes(db, query.as_ref())?.iter()?.collect()
+}
+
+// Returns None if the file was emptied.
+fn edit_until_valid(file: &Path) -> Result<Option<Vec<DbNote>>> {
+    loop {
+        let status = run_editor(file)
+            .context("Running $EDITOR")?;
+        if !status.success() {
+            bail!("Editor exited with {}", status);
+        }
+
+        let text = fs::read_to_string(file)?;
+        let blank = text.lines()
+            .all(|l| l.trim().is_empty() || l.trim_start().starts_with('#'));
+        if blank {
+            return Ok(None);
+        }
+        let errors = match read_yaml_notes(text.as_bytes()) {
+            Ok(notes) => {
+                let mut valid = Vec::new();
+                let mut errors = Vec::new();
+                for n in notes.iter() {
+                    match (n.to_db_note(), n.uuid) {
+                        (Ok(note), _) => valid.push(note),
+                        (Err(e), Some(uuid)) => errors.push(format!("note {}: {}", uuid, e)),
+                        (Err(e), None) => errors.push(format!("new note: {}", e)),
+                    }
+                }
+                if errors.is_empty() {
+                    return Ok(Some(valid));
+                }
+                errors
+            }
+            Err(errors) => errors,
+        };
+
+        // Errors are appended to keep line numbers in them right.
+        let mut text: String = text.lines()
+            .filter(|l| !l.starts_with(ERROR_PREFIX))
+            .map(|l| format!("{}\n", l))
+            .collect();
+        text.push_str(&format!("{} Errors:\n", ERROR_PREFIX));
+        for e in errors {
+            text.push_str(&format!("{}   - {}\n", ERROR_PREFIX, e));
+        }
+        fs::write(file, text)?;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use chrono::Utc;
+    use indoc::indoc;
+    use crate::db::insert_notes;
+    use crate::note::NoteData;
+
+    fn note(tags: &str, data: NoteData) -> DbNote {
+        DbNote {
+            uuid: Uuid::new_v4(),
+            ctime: Utc::now(),
+            tags: tags.to_string(),
+            data,
+            parent: None,
+        }
+    }
+
+    #[test]
+    fn unedited_notes_keep_hashes() -> Result<()> {
+        let notes = vec![
+            DbNote {
+                parent: Some("abc".to_string()),
+                ..note("lang/rust\ntraits", NoteData::Text("text".to_string()))
+            },
+            note("math", NoteData::Card(vec!["2+2".to_string(), "4".to_string()])),
+        ];
+        let yaml = notes_to_yaml(notes.clone())?;
+        assert!(yaml.contains("tags: lang/rust, traits\n"));
+
+        let read = read_yaml_notes(yaml.as_bytes()).map_err(|e| anyhow::anyhow!(e.join("\n")))?
+            .iter()
+            .map(|n| n.to_db_note())
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(read.len(), 2);
+        for (n, r) in notes.iter().zip(read.iter()) {
+            assert_eq!(r.hash_and_json(), n.hash_and_json());
+            assert_eq!(r.parent, n.parent);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn find_notes_by_uuid_prefix_or_query() -> Result<()> {
+        let db = db::open(":memory:")?;
+        init_schema(&db)?;
+        let rust = DbNote {
+            uuid: Uuid::parse_str("12345678-aaaa-4aaa-8aaa-aaaaaaaaaaaa")?,
+            ..note("lang/rust", NoteData::Text("rust".to_string()))
+        };
+        let beef = DbNote {
+            uuid: Uuid::parse_str("87654321-aaaa-4aaa-8aaa-aaaaaaaaaaaa")?,
+            ..note("beef", NoteData::Text("beef".to_string()))
+        };
+        insert_notes(&db, &[rust.clone(), beef.clone()])?;
+
+        assert_eq!(find_notes(&db, &["1234".to_string()])?, vec![rust.clone()]);
+        assert_eq!(find_notes(&db, &["lang".to_string()])?, vec![rust.clone()]);
+        // Looks like a UUID prefix, but no note has it.
+        assert_eq!(find_notes(&db, &["beef".to_string()])?, vec![beef]);
+        assert!(find_notes(&db, &[]).is_err());
+        assert!(find_notes(&db, &["(".to_string()]).is_err());
+        Ok(())
+    }
+
+    // Makes the script the $EDITOR, the file is passed as `$1`.
+    fn set_editor(dir: &Path, script: &str) -> Result<()> {
+        let editor = dir.join("editor.sh");
+        fs::write(&editor, format!("#!/bin/sh\n{}", script))?;
+        fs::set_permissions(&editor, fs::Permissions::from_mode(0o755))?;
+        env::set_var("EDITOR", &editor);
+        Ok(())
+    }
+
+    // All the editor runs are in a single test, as $EDITOR is shared by tests.
+    #[test]
+    fn edit_until_valid_shows_errors() -> Result<()> {
+        let dir = env::temp_dir().join(format!("fhmp-test-{}", Uuid::new_v4()));
+        fs::create_dir(&dir)?;
+        let res = (|| -> Result<()> {
+            let file = dir.join("notes.yaml");
+            fs::write(&file, indoc! {"
+                # comment
+                tags: a
+                data: !card [only]
+            "})?;
+            // Each run saves a copy of the file, the note is fixed on the
+            // third run.
+            set_editor(&dir, indoc! {r#"
+                n=$(ls "$1".* 2>/dev/null | wc -l)
+                cp "$1" "$1.$((n + 1))"
+                if [ $n -eq 2 ]; then
+                    printf 'tags: a\ndata: !text fixed\n' > "$1"
+                fi
+            "#})?;
+            let notes = edit_until_valid(&file)?.expect("notes are valid");
+            assert_eq!(notes.len(), 1);
+            assert_eq!(notes[0].data, NoteData::Text("fixed".to_string()));
+
+            let expected = indoc! {"
+                # comment
+                tags: a
+                data: !card [only]
+                #! Errors:
+                #!   - new note: `card` must have two or more elments
+            "};
+            // Errors of the previous run are replaced.
+            assert_eq!(fs::read_to_string(dir.join("notes.yaml.2"))?, expected);
+            assert_eq!(fs::read_to_string(dir.join("notes.yaml.3"))?, expected);
+
+            // Emptied file cancels editing.
+            fs::write(&file, expected)?;
+            set_editor(&dir, "printf '# nothing\\n\\n' > \"$1\"")?;
+            assert!(edit_until_valid(&file)?.is_none());
+
+            set_editor(&dir, "exit 1")?;
+            assert!(edit_until_valid(&file).is_err());
+            Ok(())
+        })();
+        fs::remove_dir_all(&dir)?;
+        res
+    }
+}
@@ -0,0 +1,24 @@
+use std::path::Path;
+use anyhow::{Context, Result};
+use crate::anki;
+use crate::config::read_config;
+use crate::db::{self, init_schema, matching_notes};
+use crate::query;
+
+// Exports current notes matching the query into an Anki package.
+pub fn exec_anki(query: Option<&str>, file: &str) -> Result<()> {
+    let query = query.map(query::parse).transpose()
+        .context("Invalid query")?;
+    let cfg = read_config()
+        .context("Reading config")?;
+    let db = db::open(&cfg.db_path)
+        .context("Opening database file")?;
+    init_schema(&db)
+        .context("Initializing database schema")?;
+
+    let notes = matching_notes(&db, query.as_ref())?.iter()?.collect::<Result<Vec<_>>>()?;
+    let n = anki::export(&notes, Path::new(file))
+        .context("Writing Anki package")?;
+    println!("Exported {} notes.", n);
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use anyhow::{bail, Context, Result};
+use rusqlite::Connection;
+use uuid::Uuid;
+
+use crate::config::read_config;
+use crate::db::{self, init_schema, note_versions, revert_note, find_uuid, NoteVersion};
+use crate::note::{diff, NoteData};
+
+fn open_db() -> Result<Connection> {
+    let cfg = read_config()
+        .context("Reading config")?;
+    let db = db::open(&cfg.db_path)
+        .context("Opening database file")?;
+    init_schema(&db)
+        .context("Initializing database schema")?;
+    Ok(db)
+}
+
+// Notes and versions can be given by unique prefixes of UUIDs and hashes.
+fn find_note(db: &Connection, prefix: &str) -> Result<(Uuid, Vec<NoteVersion>)> {
+    let uuid = find_uuid(db, prefix)?;
+    Ok((uuid, note_versions(db, &uuid)?))
+}
+
+fn find_version<'a>(versions: &'a [NoteVersion], prefix: &str) -> Result<&'a NoteVersion> {
+    let found: Vec<_> = versions.iter()
+        .filter(|v| v.hash.starts_with(prefix))
+        .collect();
+    match found.as_slice() {
+        [v] => Ok(v),
+        [] => bail!("No version {}", prefix),
+        _ => bail!("Hash prefix {} matches {} versions", prefix, found.len()),
+    }
+}
+
+// Lists versions of the note, the oldest first.
+pub fn exec(prefix: &str) -> Result<()> {
+    let db = open_db()?;
+    let (uuid, versions) = find_note(&db, prefix)?;
+    println!("Note {}, created {}", uuid, versions[0].note.ctime.to_rfc3339());
+    for v in versions.iter() {
+        let text = match &v.note.data {
+            NoteData::Text(text) => text,
+            NoteData::Card(sides) => &sides[0],
+        };
+        println!("\n{}  {}", v.hash, v.status);
+        if let Some(mtime) = &v.mtime {
+            let label = if v.status == "deleted" { "deleted" } else { "retired" };
+            println!("  {} {}", label, mtime);
+        }
+        if let Some(parent) = &v.parent {
+            println!("  parent  {}", parent);
+        }
+        println!("  tags    {}", v.note.tags.replace('\n', ", "));
+        println!("  {}", text.lines().next().unwrap_or(""));
+    }
+    Ok(())
+}
+
+// Without hashes the current version is compared with its parent, or with
+// the last retired version if it has no parent.
+pub fn exec_diff(prefix: &str, hashes: Option<(&str, &str)>) -> Result<()> {
+    let db = open_db()?;
+    let (_, versions) = find_note(&db, prefix)?;
+    let (old, new) = match hashes {
+        Some((a, b)) => (find_version(&versions, a)?, find_version(&versions, b)?),
+        None => {
+            let new = versions.iter()
+                .rfind(|v| ["active", "suspended", "buried"].contains(&v.status.as_str()))
+                .unwrap_or(&versions[versions.len() - 1]);
+            let parent = new.parent.as_deref().unwrap_or("");
+            let old = versions.iter().find(|v| v.hash == parent)
+                .or_else(|| versions.iter()
+                    .filter(|v| v.hash != new.hash && v.mtime.is_some())
+                    .max_by_key(|v| v.mtime.clone()));
+            match old {
+                Some(old) => (old, new),
+                None => bail!("The note has a single version"),
+            }
+        }
+    };
+    print!("{}", diff(&old.note, &new.note, &old.hash, &new.hash));
+    Ok(())
+}
+
+// The data of the older version is saved as a new version, so the revert
+// is synced like an edit.
+pub fn exec_revert(prefix: &str, hash: &str) -> Result<()> {
+    let db = open_db()?;
+    let (uuid, versions) = find_note(&db, prefix)?;
+    let version = find_version(&versions, hash)?;
+    let data = |v: &NoteVersion| v.note.hash_and_json().0;
+    let current = versions.iter()
+        .find(|v| ["active", "suspended", "buried"].contains(&v.status.as_str()));
+    if current.map(data) == Some(data(version)) {
+        println!("Version {} is already current.", version.hash);
+        return Ok(());
+    }
+    revert_note(&db, &uuid, &version.hash)?;
+    println!("Note {} is reverted to version {}.", uuid, version.hash);
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use std::{fs, path::Path};
+use anyhow::{Context, Result};
+use rusqlite::Connection;
+use crate::anki;
+use crate::config::read_config;
+use crate::db::{self, init_schema};
+use crate::webapp::{self, ImportStats, WebData};
+
+// Imports `{notes, reviews}` in the format of the web app sync protocol.
+// Importing the same file again changes nothing.
+pub fn exec_webapp(file: &str) -> Result<()> {
+    let cfg = read_config()
+        .context("Reading config")?;
+    let db = db::open(&cfg.db_path)
+        .context("Opening database file")?;
+    init_schema(&db)
+        .context("Initializing database schema")?;
+
+    let stats = import_webapp(&db, Path::new(file))?;
+    println!("Imported {} notes and {} reviews.", stats.notes, stats.reviews);
+    for (result, n) in stats.skipped.iter() {
+        println!("Skipped {} reviews with unknown result \"{}\".", n, result);
+    }
+    Ok(())
+}
+
+// `lastReview` of notes is not stored, the time of the last review comes
+// from `reviews`. It only tells if `nextReview` takes all reviews into account.
+fn import_webapp(db: &Connection, file: &Path) -> Result<ImportStats> {
+    let data: WebData = serde_json::from_reader(fs::File::open(file)?)
+        .context("Parsing web app data")?;
+    webapp::import(db, &data, webapp::WEBAPP_TAG)
+}
+
+// Imports notes from an Anki package, and also their review history if
+// `with_reviews` is set.
+pub fn exec_anki(file: &str, with_reviews: bool) -> Result<()> {
+    let cfg = read_config()
+        .context("Reading config")?;
+    let db = db::open(&cfg.db_path)
+        .context("Opening database file")?;
+    init_schema(&db)
+        .context("Initializing database schema")?;
+
+    let stats = anki::import(&db, &cfg, Path::new(file), with_reviews)?;
+    println!("Imported {} notes and {} reviews.", stats.notes, stats.reviews);
+    for (note_type, n) in stats.skipped.iter() {
+        println!("Skipped {} notes of unsupported type \"{}\".", n, note_type);
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use indoc::indoc;
+    use uuid::Uuid;
+
+    fn time(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn import_webapp_file() -> Result<()> {
+        let file = std::env::temp_dir().join(format!("fhmp-test-{}.json", Uuid::new_v4()));
+        fs::write(&file, indoc! {r#"
+            {
+              "notes": [
+                {
+                  "id": "lp2d5a1c.x1",
+                  "text": "2+2\n----\n4",
+                  "ver": "lp2d5a1c",
+                  "lastReview": "2024-01-02T10:00:00.000Z",
+                  "nextReview": "2024-01-05T10:00:00.000Z"
+                }
+              ],
+              "reviews": [
+                {"note": "lp2d5a1c.x1", "time": "2024-01-01T09:00:00.000Z", "result": "hard"},
+                {"note": "lp2d5a1c.x1", "time": "2024-01-02T10:00:00.000Z", "result": "good"}
+              ]
+            }
+        "#})?;
+        let db = db::open(":memory:")?;
+        init_schema(&db)?;
+        let first = import_webapp(&db, &file);
+        let second = import_webapp(&db, &file);
+        fs::remove_file(&file)?;
+
+        let first = first?;
+        assert_eq!((first.notes, first.reviews), (1, 2));
+        let second = second?;
+        assert_eq!((second.notes, second.reviews), (0, 0));
+
+        let notes = db::active_notes(&db)?.iter()?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(notes.len(), 1);
+        let uuid = notes[0].uuid;
+        assert_eq!(db::note_versions(&db, &uuid)?.len(), 1);
+        let history = db::review_history(&db, &uuid)?;
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].ctime, time("2024-01-02T10:00:00Z"));
+        let entry = db::queue_entry(&db, &uuid)?;
+        assert_eq!(entry.last_review, time("2024-01-02T10:00:00Z"));
+        assert_eq!(entry.next_review, time("2024-01-05T10:00:00Z"));
+        Ok(())
+    }
+}
@@ -33,7 +33,7 @@ fn create_new_dir(path: &Path) -> Result<PathBuf> {
     }
 }
 
-fn run_editor(file: &Path) -> Result<process::ExitStatus> {
+pub fn run_editor(file: &Path) -> Result<process::ExitStatus> {
     let editor = env::var("EDITOR")?;
     process::Command::new(editor)
         .arg(file)
@@ -1,39 +1,145 @@
+use std::time::Instant;
 use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Local, Timelike, Utc};
 use dialoguer::{theme::ColorfulTheme, FuzzySelect};
+use rusqlite::Connection;
+use serde_json::json;
 
-use crate::config::read_config;
+use crate::config::{read_config, CliConfig, Limits};
 use crate::note::{DbNote, NoteData};
-use crate::db::{init_schema, select_notes_for_review};
+use crate::db::{
+    self, count_due_notes, count_reviews_since, init_schema, queue_entry,
+    review_history, review_results, review_stats_since, save_review,
+    select_notes_for_review, set_note_status, unbury_notes, NoteStatus, ReviewStats
+};
+use crate::policy::{resolve_policy, Policy};
+use crate::query::{self, Query};
+use crate::scheduler::{Review, ReviewResult};
 
-pub enum ReviewResult {
-    Easy, Hard, Again
+fn start_of_today() -> DateTime<Utc> {
+    let now = Local::now();
+    (now - Duration::seconds(now.num_seconds_from_midnight() as i64))
+        .with_timezone(&Utc)
 }
 
-fn get_review_result() -> Result<Option<ReviewResult>> {
+fn daily_cap_reached(
+    db: &Connection,
+    policy: &Policy,
+    today: &DateTime<Utc>
+) -> Result<bool> {
+    match policy.daily_cap {
+        None => Ok(false),
+        Some(cap) => {
+            let n = count_reviews_since(db, today, Some(policy.name()))?;
+            Ok(n >= cap)
+        }
+    }
+}
+
+// Returns description of the exhausted daily limit, if any.
+fn budget_spent(limits: &Limits, stats: &ReviewStats) -> Option<String> {
+    match (limits.reviews, limits.minutes) {
+        (Some(n), _) if stats.reviews >= n =>
+            Some(format!("{} reviews", n)),
+        (_, Some(m)) if stats.seconds >= m * 60 =>
+            Some(format!("{} minutes", m)),
+        _ => None,
+    }
+}
+
+fn schedule_next_review(
+    db: &Connection,
+    cfg: &CliConfig,
+    note: &DbNote,
+    result: ReviewResult,
+    duration: std::time::Duration
+) -> Result<()> {
+    let now = Utc::now();
+    let entry = queue_entry(db, &note.uuid)?;
+    let mut history = review_history(db, &note.uuid)?;
+    history.push(Review { ctime: now, result });
+
+    let policy = resolve_policy(&cfg.policy, &note.tags);
+    let scheduler = policy.map_or(cfg.scheduler.scheduler(), Policy::scheduler);
+    let next_review = cfg.next_review(note, &history);
+
+    let old_interval = entry.next_review - entry.last_review;
+    let actual_interval = now - entry.last_review;
+    let new_interval = next_review - now;
+    let decision = json!({
+        "policy": policy.map(Policy::name),
+        "scheduler": scheduler.name(),
+        "params": scheduler.params(),
+        "old_interval": old_interval.num_seconds(),
+        "actual_interval": actual_interval.num_seconds(),
+        "new_interval": new_interval.num_seconds(),
+        "duration": duration.as_secs(),
+    });
+    save_review(db, &note.uuid, &now, result.label(), &decision, &next_review)
+}
+
+// Grades available in the review menu ordered from the worst to the best.
+// Grades are taken from the `review_result` dictionary and filtered by
+// the user config.
+fn grades(db: &Connection, cfg: &CliConfig) -> Result<Vec<ReviewResult>> {
+    let known = review_results(db)?;
+    if let Some(g) = cfg.grades.iter().find(|g| !known.contains(g)) {
+        anyhow::bail!("Unknown grade `{}` in config, expected one of: {}",
+            g, known.join(", "));
+    }
+    known.iter()
+        .filter(|g| cfg.grades.contains(g))
+        .map(|g| ReviewResult::from_label(g))
+        .collect()
+}
+
+enum Outcome {
+    // Don't save any result.
+    Skip,
+    Grade(ReviewResult),
+    // Exclude the note from reviews until `fhmp unsuspend`.
+    Suspend,
+}
+
+fn get_review_result(grades: &[ReviewResult]) -> Result<Outcome> {
     let theme = ColorfulTheme::default();
+    // Best grade goes first as it is the most frequent one.
+    let items: Vec<_> = grades.iter().rev()
+        .enumerate()
+        .map(|(i, g)| format!("{} {}", i + 1, capitalize(g.label())))
+        .collect();
     let res = FuzzySelect::with_theme(&theme)
         .default(0)
-        .item("0 Skip")  // don't save any result
-        .item("1 Easy")  // increase delay
-        .item("2 Hard")  // decrease delay
-        .item("3 Again") // delay for 5 minutes
+        .item("0 Skip")
+        .items(&items)
+        .item("Suspend")
         .interact()?;
 
     Ok(match res {
-        1 => Some(ReviewResult::Easy),
-        2 => Some(ReviewResult::Hard),
-        3 => Some(ReviewResult::Again),
-        _ => None,
+        0 => Outcome::Skip,
+        i if i > grades.len() => Outcome::Suspend,
+        i => Outcome::Grade(grades[grades.len() - i]),
     })
 }
 
-fn review_note(note: &DbNote) -> Result<Option<ReviewResult>> {
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn review_note(
+    note: &DbNote,
+    grades: &[ReviewResult]
+) -> Result<Outcome> {
     let theme = ColorfulTheme::default();
     println!("\n#{}", note.tags);
     match &note.data {
         NoteData::Text(txt) => {
             println!("{}", txt);
-            get_review_result()
+            get_review_result(grades)
         },
         NoteData::Card(card) => {
             println!("{}", card[0]);
@@ -42,33 +148,80 @@ fn review_note(note: &DbNote) -> Result<Option<ReviewResult>> {
                 .default(0)
                 .item("1 Show the answer")
                 .item("2 That was easy")
+                .item("3 Suspend")
                 .interact()?;
 
             if res == 1 {
-                Ok(Some(ReviewResult::Easy))
+                // the best of available grades
+                Ok(grades.last().copied().map_or(Outcome::Skip, Outcome::Grade))
+            } else if res == 2 {
+                Ok(Outcome::Suspend)
             } else {
                 for txt in &card[1..] {
                     println!("{}", txt);
                 }
-                get_review_result()
+                get_review_result(grades)
             }
         }
     }
 }
 
-pub fn exec(tags: &[String]) -> Result<()> {
+pub fn exec(args: &[String]) -> Result<()> {
+    let query = query::from_args(args)
+        .context("Invalid query")?;
     let cfg = read_config()
         .context("Reading config")?;
-    let db = sqlite::open(&cfg.db_path)
+    let db = db::open(&cfg.db_path)
         .context("Opening database file")?;
     init_schema(&db)
         .context("Initializing database schema")?;
 
-    let notes = select_notes_for_review(&db, tags)?;
+    unbury_notes(&db)?;
+    let notes = select_notes_for_review(&db, query.as_ref(), cfg.limits.session)?;
+    review_notes(&db, &cfg, &notes, query.as_ref())
+}
+
+// Reviews the notes within daily limits. Notes left due are counted by
+// the query.
+pub fn review_notes(
+    db: &Connection,
+    cfg: &CliConfig,
+    notes: &[DbNote],
+    query: Option<&Query>
+) -> Result<()> {
+    let grades = grades(db, cfg)?;
+    let today = start_of_today();
 
     for note in notes.iter() {
-        if let Some(res) = review_note(note)? {
-            // FIXME: save result and schedule next review
+        let stats = review_stats_since(db, &today)?;
+        if let Some(limit) = budget_spent(&cfg.limits, &stats) {
+            let left = count_due_notes(db, query)?;
+            println!("\nDaily limit of {} is reached.", limit);
+            println!("{} notes are left for tomorrow.", left);
+            break;
+        }
+        if let Some(n) = cfg.limits.new_notes {
+            if stats.new_notes >= n && review_history(db, &note.uuid)?.is_empty() {
+                continue;
+            }
+        }
+        if let Some(policy) = resolve_policy(&cfg.policy, &note.tags) {
+            if daily_cap_reached(db, policy, &today)? {
+                continue;
+            }
+        }
+
+        let started = Instant::now();
+        match review_note(note, &grades)? {
+            Outcome::Skip => {},
+            Outcome::Grade(res) => {
+                schedule_next_review(db, cfg, note, res, started.elapsed())
+                    .context("Saving review result")?;
+            }
+            Outcome::Suspend => {
+                set_note_status(db, &note.uuid, &NoteStatus::Suspended)?;
+                println!("Suspended, `fhmp unsuspend {}` to review it again.", note.uuid);
+            }
         }
     }
     Ok(())
@@ -0,0 +1,58 @@
+use anyhow::{bail, Context, Result};
+use dialoguer::console;
+
+use crate::cmd_review::review_notes;
+use crate::config::read_config;
+use crate::db::{self, init_schema, search_notes, unbury_notes};
+
+const MAX_RESULTS: usize = 20;
+
+// Prints current notes matching all the words, the best matches first.
+// With `--review` the active ones are reviewed even if not due yet.
+pub fn exec(args: &[String]) -> Result<()> {
+    let review = args.iter().any(|a| a == "--review");
+    let words: Vec<&str> = args.iter()
+        .filter(|a| *a != "--review")
+        .map(String::as_str)
+        .collect();
+    if words.is_empty() {
+        bail!("Nothing to search for");
+    }
+    let cfg = read_config()
+        .context("Reading config")?;
+    let db = db::open(&cfg.db_path)
+        .context("Opening database file")?;
+    init_schema(&db)
+        .context("Initializing database schema")?;
+    unbury_notes(&db)?;
+
+    let markers = if console::colors_enabled() {
+        ("\x1b[1;4m", "\x1b[0m")
+    } else {
+        ("[", "]")
+    };
+    let limit = if review { cfg.limits.session } else { MAX_RESULTS };
+    let found = search_notes(&db, &words.join(" "), markers, limit)
+        .context("Searching notes")?;
+    if found.is_empty() {
+        println!("No notes found.");
+        return Ok(());
+    }
+
+    if review {
+        let notes: Vec<_> = found.into_iter()
+            .filter(|r| r.status == "active")
+            .map(|r| r.note)
+            .collect();
+        return review_notes(&db, &cfg, &notes, None);
+    }
+    for r in found.iter() {
+        let status = if r.status == "active" { String::new() } else { format!("  ({})", r.status) };
+        println!("{}  #{}{}", r.note.uuid, r.note.tags.replace('\n', ", "), status);
+        println!("    {}\n", r.snippet.split_whitespace().collect::<Vec<_>>().join(" "));
+    }
+    if found.len() == limit {
+        println!("Only the first {} notes are shown.", limit);
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use anyhow::{anyhow, Context, Result};
+use rusqlite::Connection;
+use serde::de::DeserializeOwned;
+use tiny_http::{Header, Method, Request, Response, Server};
+use crate::config::{read_config, CliConfig};
+use crate::db::{self, init_schema};
+use crate::sync;
+use crate::webapp;
+
+// Serves the web app sync protocol:
+//   - `POST /<client_key>` with `{notes, reviews}` merges them into the DB;
+//   - `GET /<client_key>` returns all current notes and reviews.
+// and `fhmp sync` with `POST /<client_key>/sync` (see sync.rs).
+// Requests are handled one by one, so there are no concurrent writes.
+pub fn exec() -> Result<()> {
+    let cfg = read_config()
+        .context("Reading config")?;
+    let server_cfg = cfg.server.as_ref()
+        .context("`[server]` section is missing in the config")?;
+    let db = db::open(&cfg.db_path)
+        .context("Opening database file")?;
+    init_schema(&db)
+        .context("Initializing database schema")?;
+
+    let server = Server::http(&server_cfg.listen)
+        .map_err(|e| anyhow!("Listening on {}: {}", server_cfg.listen, e))?;
+    println!("Listening on http://{}", server_cfg.listen);
+
+    let path = format!("/{}", server_cfg.client_key);
+    for mut req in server.incoming_requests() {
+        let (status, body) = match handle(&db, &cfg, &path, &mut req) {
+            Ok(res) => res,
+            Err(e) => {
+                eprintln!("{} {}: {:#}", req.method(), req.url(), e);
+                (500, json_string(&e.to_string()))
+            }
+        };
+        let rsp = Response::from_string(body)
+            .with_status_code(status)
+            .with_header(header("Content-Type", "application/json"));
+        let rsp = cors_headers().into_iter()
+            .fold(rsp, |rsp, h| rsp.with_header(h));
+        if let Err(e) = req.respond(rsp) {
+            eprintln!("Sending response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn handle(
+    db: &Connection,
+    cfg: &CliConfig,
+    key_path: &str,
+    req: &mut Request
+) -> Result<(u16, String)> {
+    let url_path = req.url().split('?').next().unwrap_or_default();
+    let Some(route) = url_path.trim_end_matches('/').strip_prefix(key_path) else {
+        return Ok((404, "\"Not found\"".to_string()));
+    };
+
+    match (route, req.method()) {
+        // CORS preflight
+        (_, Method::Options) => Ok((204, String::new())),
+        ("", Method::Get) => {
+            let data = webapp::export(db)?;
+            Ok((200, serde_json::to_string(&data)?))
+        },
+        ("", Method::Post) => {
+            let data: webapp::WebData = match parse_body(req)? {
+                Ok(data) => data,
+                Err(e) => return Ok((400, json_string(&e.to_string()))),
+            };
+            let stats = webapp::import(db, &data, webapp::WEBAPP_TAG)?;
+            println!("Received {} notes and {} reviews", stats.notes, stats.reviews);
+            for (result, n) in stats.skipped.iter() {
+                println!("Skipped {} reviews with unknown result \"{}\"", n, result);
+            }
+            Ok((200, "{}".to_string()))
+        },
+        ("/sync", Method::Post) => {
+            let sync_req: sync::SyncRequest = match parse_body(req)? {
+                Ok(r) => r,
+                Err(e) => return Ok((400, json_string(&e.to_string()))),
+            };
+            let rsp = sync::exchange(db, cfg, &sync_req)?;
+            println!("Synced {} notes and {} reviews",
+                sync_req.changes.notes.len(), sync_req.changes.reviews.len());
+            Ok((200, serde_json::to_string(&rsp)?))
+        },
+        ("" | "/sync", _) => Ok((405, "\"Method not allowed\"".to_string())),
+        _ => Ok((404, "\"Not found\"".to_string())),
+    }
+}
+
+// Malformed JSON is reported to the client rather than logged.
+fn parse_body<T: DeserializeOwned>(req: &mut Request) -> Result<serde_json::Result<T>> {
+    let mut body = String::new();
+    req.as_reader().read_to_string(&mut body)?;
+    Ok(serde_json::from_str(&body))
+}
+
+// Error messages are sent as JSON strings.
+fn json_string(s: &str) -> String {
+    serde_json::Value::from(s).to_string()
+}
+
+// The web app is served from another origin.
+fn cors_headers() -> Vec<Header> {
+    vec![
+        header("Access-Control-Allow-Origin", "*"),
+        header("Access-Control-Allow-Methods", "GET, POST, OPTIONS"),
+        header("Access-Control-Allow-Headers", "Content-Type"),
+    ]
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name, value)
+        .expect("Header name and value are valid ASCII")
+}
@@ -0,0 +1,81 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+use rusqlite::Connection;
+
+use crate::config::read_config;
+use crate::db::{self, find_uuid, init_schema, set_note_status, NoteStatus};
+
+fn open_db() -> Result<Connection> {
+    let cfg = read_config()
+        .context("Reading config")?;
+    let db = db::open(&cfg.db_path)
+        .context("Opening database file")?;
+    init_schema(&db)
+        .context("Initializing database schema")?;
+    Ok(db)
+}
+
+// Notes are given by unique prefixes of UUIDs. All of them are checked
+// before any status is changed.
+fn set_status(prefixes: &[String], status: &NoteStatus, done: &str) -> Result<()> {
+    if prefixes.is_empty() {
+        bail!("No notes given");
+    }
+    let db = open_db()?;
+    let uuids = prefixes.iter()
+        .map(|p| find_uuid(&db, p))
+        .collect::<Result<Vec<_>>>()?;
+    let tx = db.unchecked_transaction()?;
+    for uuid in uuids.iter() {
+        if set_note_status(&tx, uuid, status)? {
+            println!("Note {} is {}.", uuid, done);
+        } else {
+            println!("Note {} has no current version, see `fhmp history`.", uuid);
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+// Deleted notes keep their versions, `fhmp revert` brings them back.
+// `fhmp sync` deletes them in other databases as well.
+pub fn exec_rm(prefixes: &[String]) -> Result<()> {
+    set_status(prefixes, &NoteStatus::Deleted, "deleted")
+}
+
+pub fn exec_suspend(prefixes: &[String]) -> Result<()> {
+    set_status(prefixes, &NoteStatus::Suspended, "suspended")
+}
+
+// Makes suspended and buried notes active again.
+pub fn exec_unsuspend(prefixes: &[String]) -> Result<()> {
+    set_status(prefixes, &NoteStatus::Active, "active")
+}
+
+// Notes are buried until the local midnight, or until the start of the
+// given day.
+pub fn exec_bury(args: &[String]) -> Result<()> {
+    let (day, prefixes) = match args {
+        [opt, day, rest @ ..] if opt == "--until" => {
+            let day = NaiveDate::parse_from_str(day, "%Y-%m-%d")
+                .with_context(|| format!("Invalid date \"{}\", expected YYYY-MM-DD", day))?;
+            (day, rest)
+        }
+        _ => (Local::today().naive_local().succ(), args),
+    };
+    let until = start_of_day(day)?;
+    if until <= Utc::now() {
+        bail!("The date must be in the future");
+    }
+    let done = format!("buried until {}", until.with_timezone(&Local).format("%Y-%m-%d %H:%M"));
+    set_status(prefixes, &NoteStatus::Buried(until), &done)
+}
+
+fn start_of_day(day: NaiveDate) -> Result<DateTime<Utc>> {
+    let time = day.and_time(NaiveTime::from_hms(0, 0, 0));
+    // Midnight may be skipped by a DST transition.
+    match Local.from_local_datetime(&time).earliest() {
+        Some(t) => Ok(t.with_timezone(&Utc)),
+        None => bail!("No midnight on {} in the local time zone", day),
+    }
+}
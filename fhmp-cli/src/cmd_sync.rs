@@ -0,0 +1,63 @@
+use std::path::Path;
+use anyhow::{anyhow, Context, Result};
+use crate::config::read_config;
+use crate::db::{self, init_schema};
+use crate::sync::{self, SyncRequest, SyncResponse};
+
+// Syncs with another database, either a file or `fhmp serve` at
+// `http://host:port/<client_key>`. fhmp is built without TLS, so HTTPS
+// remotes are rejected rather than failing in the request.
+pub fn exec(remote: &str) -> Result<()> {
+    if remote.starts_with("https://") {
+        anyhow::bail!("HTTPS is not supported, use http:// or a proxy that terminates TLS");
+    }
+    let cfg = read_config()
+        .context("Reading config")?;
+    let db = db::open(&cfg.db_path)
+        .context("Opening database file")?;
+    init_schema(&db)
+        .context("Initializing database schema")?;
+
+    let stats = if remote.starts_with("http://") {
+        sync::sync(&db, &cfg, remote, |req| send(remote, req))?
+    } else {
+        // Opening a mistyped path would create an empty database.
+        let path = Path::new(remote);
+        if !path.is_file() {
+            anyhow::bail!("Remote database file {} does not exist", remote);
+        }
+        if path.canonicalize()? == Path::new(&cfg.db_path).canonicalize()? {
+            anyhow::bail!("Can't sync the database with itself");
+        }
+        let remote_db = db::open(remote)
+            .context("Opening remote database file")?;
+        init_schema(&remote_db)
+            .context("Initializing remote database schema")?;
+        // Relative paths would make different remotes from the same file.
+        let name = path.canonicalize()?.to_string_lossy().to_string();
+        sync::sync(&db, &cfg, &name, |req| sync::exchange(&remote_db, &cfg, req))?
+    };
+
+    println!("Sent {} notes and {} reviews.", stats.sent_notes, stats.sent_reviews);
+    println!("Received {} new notes and {} new reviews.",
+        stats.received_notes, stats.received_reviews);
+    if stats.updated_notes > 0 {
+        println!("Updated the status of {} notes.", stats.updated_notes);
+    }
+    if stats.conflicts > 0 {
+        println!("{} received notes conflict with local edits, see `fhmp conflicts`.",
+            stats.conflicts);
+    }
+    Ok(())
+}
+
+fn send(url: &str, req: &SyncRequest) -> Result<SyncResponse> {
+    let url = format!("{}/sync", url.trim_end_matches('/'));
+    let body = ureq::post(&url)
+        .set("Content-Type", "application/json")
+        .send_string(&serde_json::to_string(req)?)
+        .map_err(|e| anyhow!("Sync request failed: {}", e))?
+        .into_string()?;
+    serde_json::from_str(&body)
+        .context("Parsing response")
+}
@@ -0,0 +1,23 @@
+use anyhow::{Context, Result};
+use crate::config::read_config;
+use crate::db::{self, init_schema, tag_stats, unbury_notes};
+
+// Prints the tree of tags with number of notes and number of notes due for
+// review for each node.
+pub fn exec() -> Result<()> {
+    let cfg = read_config()
+        .context("Reading config")?;
+    let db = db::open(&cfg.db_path)
+        .context("Opening database file")?;
+    init_schema(&db)
+        .context("Initializing database schema")?;
+    unbury_notes(&db)?;
+
+    println!("{:>6} {:>6}", "notes", "due");
+    for s in tag_stats(&db)? {
+        let depth = s.tag.matches('/').count();
+        let name = s.tag.rsplit('/').next().unwrap_or_default();
+        println!("{:>6} {:>6} {}{}", s.notes, s.due, "  ".repeat(depth), name);
+    }
+    Ok(())
+}
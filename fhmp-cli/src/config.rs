@@ -2,11 +2,79 @@ use std::{env, path::PathBuf};
 use anyhow::{Context, Result};
 use config::{Config, File, FileFormat};
 use serde::Deserialize;
+use chrono::{DateTime, Utc};
+use crate::note::DbNote;
+use crate::policy::{resolve_policy, Policy};
+use crate::scheduler::{Review, SchedulerConfig};
 
 #[derive(Deserialize)]
 pub struct CliConfig {
     pub db_path: String,
     pub data_path: PathBuf,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+    #[serde(default)]
+    pub policy: Vec<Policy>,
+    #[serde(default)]
+    pub limits: Limits,
+    // Review results to choose from, labels from the `review_result` table.
+    // E.g. `grades = ["again", "hard", "easy"]` for a 3-grade scale.
+    #[serde(default = "default_grades")]
+    pub grades: Vec<String>,
+    pub server: Option<ServerConfig>,
+}
+
+impl CliConfig {
+    // Next review of the note by the policy matching its tags or by the
+    // default scheduler.
+    pub fn next_review(&self, note: &DbNote, history: &[Review]) -> DateTime<Utc> {
+        match resolve_policy(&self.policy, &note.tags) {
+            Some(p) => p.next_review(note.ctime, history),
+            None => self.scheduler.scheduler().next_review(note.ctime, history),
+        }
+    }
+}
+
+fn default_grades() -> Vec<String> {
+    ["again", "hard", "good", "easy"].map(String::from).to_vec()
+}
+
+// `fhmp serve` settings:
+//
+//     [server]
+//     listen = "0.0.0.0:8765"
+//     client_key = "some-long-random-string"
+//
+// The web app is configured with `SYNC_SERVER_URL = "http://host:8765"` and
+// the same `CLIENT_KEY`.
+#[derive(Deserialize)]
+pub struct ServerConfig {
+    pub listen: String,
+    pub client_key: String,
+}
+
+// Limits on the amount of work done by `fhmp review`.
+// Daily limits are checked against reviews saved since the local midnight.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    // Max number of notes selected for a single session.
+    pub session: usize,
+    // Max number of notes reviewed for the first time per day.
+    pub new_notes: Option<usize>,
+    pub reviews: Option<usize>,
+    pub minutes: Option<i64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            session: 10,
+            new_notes: None,
+            reviews: None,
+            minutes: None,
+        }
+    }
 }
 
 pub fn read_config() -> Result<CliConfig> {
@@ -1,135 +1,333 @@
+use std::sync::Arc;
 use anyhow::{anyhow, Result};
 use chrono::{DateTime, Utc};
+use regex::Regex;
+use rusqlite::{functions::FunctionFlags, params, Connection, OptionalExtension, Statement};
+use serde::{Deserialize, Serialize};
+use sha3::{Shake128, digest::{Update, ExtendableOutput, XofReader}};
 use uuid::Uuid;
+use crate::migrations;
 use crate::note::DbNote;
+use crate::query::Query;
+use crate::scheduler::{Review, ReviewResult};
+
+// Timestamps that are compared inside SQL queries (e.g. `queue.next_review`)
+// must have exactly the same format as `strftime('%Y-%m-%dT%H:%M:%SZ', 'now')`.
+pub fn to_db_time(t: &DateTime<Utc>) -> String {
+    t.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+fn from_db_time(s: &str) -> Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(s)?.with_timezone(&Utc))
+}
+
+// Opens database file and registers custom SQL functions.
+pub fn open(path: &str) -> Result<Connection> {
+    let db = Connection::open(path)?;
+    // Bundled SQLite enforces foreign keys by default, but old versions of
+    // the schema reference `notes(uuid)` which is not unique.
+    // Foreign keys are turned on after migrating the schema.
+    db.pragma_update(None, "foreign_keys", false)?;
+    migrations::check_version(&db)?;
+    add_regexp_function(&db)?;
+    Ok(db)
+}
+
+// `x regexp y` is a syntax for `regexp(y, x)`, but SQLite does not provide
+// an implementation for it.
+// Copied from https://docs.rs/rusqlite/latest/rusqlite/functions/
+fn add_regexp_function(db: &Connection) -> rusqlite::Result<()> {
+    db.create_scalar_function(
+        "regexp",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| {
+            assert_eq!(ctx.len(), 2, "called with unexpected number of arguments");
+            // Compiled regexp is cached while the statement is executed.
+            let regexp: Arc<Regex> = ctx.get_or_create_aux(0, |vr| -> Result<_> {
+                Ok(Regex::new(vr.as_str()?)?)
+            })?;
+            let is_match = {
+                let text = ctx
+                    .get_raw(1)
+                    .as_str()
+                    .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+                regexp.is_match(text)
+            };
+            Ok(is_match)
+        },
+    )
+}
+
+// Brings database schema up to date (see migrations.rs).
+pub fn init_schema(db: &Connection) -> Result<()> {
+    migrations::migrate(db)
+}
+
+// Note version as it is stored in the DB.
+#[derive(Serialize, Deserialize)]
+pub struct NoteRow {
+    pub hash: String,
+    pub uuid: String,
+    pub ctime: String,
+    pub mtime: Option<String>,
+    pub tags: String,
+    pub data: String,
+    // Hash of the version this one is based on.
+    pub parent: Option<String>,
+    pub status: i64,
+    #[serde(default)]
+    pub buried_until: Option<String>,
+    // Time of the last status change, none for new versions.
+    #[serde(default)]
+    pub stime: Option<String>,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Inserted {
+    // The version is already in the DB.
+    Exists,
+    New,
+    // The version is not based on the current one, e.g. the note was edited
+    // on two devices. It is kept with the 'conflict' status.
+    Conflict,
+}
+
+// Inserts a version of a note.
+// A new active version retires the current one if the current version is
+// among its ancestors. A version based on one of the conflicting versions
+// resolves the conflict. Otherwise it becomes a conflicting version.
+pub fn insert_note_row(db: &Connection, row: &NoteRow) -> Result<Inserted> {
+    let exists: bool = db.query_row(
+        "select exists (select 1 from notes where hash = ?)",
+        [&row.hash],
+        |r| r.get(0))?;
+    if exists {
+        return Ok(Inserted::Exists);
+    }
+
+    // The current version may be suspended, buried or deleted, new versions
+    // inherit that.
+    let current = current_state(db, &row.uuid)?;
+    let mut parent = row.parent.clone();
+    let is_current = matches!(row.status, 1 | 4..=6);
+    let mut status = if is_current { 1 } else { row.status };
+    let mut resolves = false;
+    match (&current, &row.parent) {
+        (Some(_), _) if status != 1 => {},
+        (None, _) => {},
+        // Based on whatever is the current version.
+        (Some(c), None) => parent = Some(c.hash.clone()),
+        (Some(c), Some(p)) => {
+            let ancestors = ancestors(db, p)?;
+            if !ancestors.contains(&c.hash) {
+                let conflicts = ancestors.iter()
+                    .map(|h| note_status(db, h))
+                    .collect::<Result<Vec<_>>>()?;
+                resolves = conflicts.contains(&Some(3));
+                if !resolves {
+                    status = 3;
+                }
+            }
+        },
+    }
+    // The newer explicit status wins, a version received from another
+    // database may be deleted, suspended or buried there.
+    let mut state = NoteState {
+        hash: row.hash.clone(),
+        status,
+        buried_until: row.buried_until.clone(),
+        stime: row.stime.clone(),
+    };
+    if status == 1 {
+        state.status = row.status;
+        if let Some(c) = current.filter(|c| c.stime >= row.stime) {
+            state = NoteState { hash: state.hash, ..c };
+        }
+    }
 
-pub fn init_schema(db: &sqlite::Connection) -> Result<()> {
     db.execute("
-        -- This table is a dictionary of note statuses.
-        create table if not exists note_status(
-            id integer primary key,
-            label text
-        );
-
-        insert or ignore into note_status (id, label) values
-            (1, 'active'),  -- is ready for review
-            (2, 'retired'); -- was updated by a newer version or deleted
-
-        -- This table holds our notes with some metadata.
-        -- Notes are read-only. Hash of tags+data is used as a primary key.
-        -- When updating a note, a new version is created and the original one
-        -- is marked as 'retired'.
-        -- UUID is used to link versions together and track history of updates.
-        create table if not exists notes(
-            hash text primary key,
-            uuid text not null,
-            ctime text not null,
-            mtime text,
-            tags text not null,
-            data json not null,
-            status integer not null references note_status(id) default 1
-        );
-        create index if not exists notes_uuid_ix
-            on notes(uuid);
-
-        create trigger if not exists retire_updated_notes
-            before insert on notes
-            begin
-                update notes
-                    set status = 2,
-                        mtime = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
-                    where true
-                      and hash <> new.hash
-                      and uuid = new.uuid
-                      and status = 1;
-            end;
-
-        create trigger if not exists add_fresh_notes_to_queue
-            after insert on notes
-            begin
-                insert into queue
-                    (note_id, next_review)
-                    values
-                    (new.uuid, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
-                on conflict (note_id) do nothing;
-            end;
-
-        create view if not exists current_notes as
-            select * from notes
-                where status = 1;
-
-        -- This table holds history of reviews.
-        -- Each row references some note and contains a review outcome.
-        create table if not exists review(
-            id integer primary key,
-            note_id text not null references notes(uuid),
-            ctime text not null,
-            result text not null,  -- FIXME: dictionary of possible results?
-            decision json not null -- free form details of the decision
-        );
-
-        -- Queue is used to select notes that are due for review.
-        create table if not exists queue(
-            note_id text not null unique references notes(uuid),
-            next_review text not null
-        );
-        create index if not exists queue_next_review_ix
-            on queue(next_review);
-    ").map_err(|e| anyhow!(e))
-}
-
-struct InsertNoteQuery<'l>(sqlite::Statement<'l>);
-
-impl<'l> InsertNoteQuery<'l> {
-    fn init(db: &'l sqlite::Connection) -> Result<Self> {
-        db.prepare("
-            insert into notes
-              (hash, uuid, ctime, tags, data)
-            values
-              (?, ?, ?, ?, ?)
-            on conflict (hash) do nothing
-        ").map_err(|e| anyhow!(e)).map(Self)
-    }
-
-    fn exec(&mut self, n: &DbNote) -> Result<()> {
-        self.0.reset()?;
-        let (hash, json) = n.hash_and_json();
-        self.0.bind(1, hash.as_str())?;
-        self.0.bind(2, n.uuid.to_string().as_str())?;
-        self.0.bind(3, n.ctime.to_rfc3339().as_str())?;
-        self.0.bind(4, n.tags.as_str())?;
-        self.0.bind(5, json.as_str())?;
-        while let sqlite::State::Row = self.0.next()? { }
-        Ok(())
+        insert into notes
+          (hash, uuid, ctime, mtime, tags, data, parent, status, buried_until, stime)
+        values
+          (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ",
+        params![row.hash, row.uuid, row.ctime, row.mtime, row.tags, row.data, parent,
+            state.status, state.buried_until, state.stime])?;
+
+    let mut insert_tag = db.prepare_cached("
+        insert or ignore into note_tags
+          (note_hash, tag)
+        values
+          (?, ?)
+    ")?;
+    for tag in row.tags.split('\n').filter(|t| !t.is_empty()) {
+        insert_tag.execute(params![row.hash, tag])?;
+    }
+
+    if resolves {
+        db.execute("
+            update notes
+                set status = 2,
+                    mtime = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+                where uuid = ? and status = 3
+            ",
+            [&row.uuid])?;
+    }
+    Ok(if status == 3 { Inserted::Conflict } else { Inserted::New })
+}
+
+// Status of the current version of a note. A deleted version is current
+// here, so that new versions of the note are deleted as well.
+struct NoteState {
+    hash: String,
+    status: i64,
+    buried_until: Option<String>,
+    stime: Option<String>,
+}
+
+fn current_state(db: &Connection, uuid: &str) -> Result<Option<NoteState>> {
+    let state = db.query_row("
+        select hash, status, buried_until, stime
+        from notes
+        where uuid = ? and status in (1, 4, 5, 6)
+        ",
+        [uuid],
+        |r| Ok(NoteState {
+            hash: r.get(0)?,
+            status: r.get(1)?,
+            buried_until: r.get(2)?,
+            stime: r.get(3)?,
+        })).optional()?;
+    Ok(state)
+}
+
+// Applies the status of a known version received from another database if
+// it was changed later than here. Returns true if the status was changed.
+//
+// Received rows must be inserted before, so that a current version retired
+// by a newer one is replaced by it as usual. A conflicting version may be
+// retired or chosen as current there with `fhmp conflicts`. Status of an
+// older version, e.g. suspended before it was edited here, applies to the
+// current one.
+pub fn merge_note_state(db: &Connection, row: &NoteRow) -> Result<bool> {
+    if row.stime.is_none() {
+        return Ok(false);
+    }
+    let Some(local) = note_status(db, &row.hash)? else {
+        return Ok(false);
+    };
+    let target = match (row.status, local) {
+        (2, 3) => row.hash.clone(),
+        (2 | 3, _) => return Ok(false),
+        (_, 1 | 3..=6) => row.hash.clone(),
+        _ => match current_state(db, &row.uuid)? {
+            Some(c) if ancestors(db, &c.hash)?.contains(&row.hash) => c.hash,
+            _ => return Ok(false),
+        },
+    };
+    let stime: Option<String> = db.query_row(
+        "select stime from notes where hash = ?",
+        [&target],
+        |r| r.get(0))?;
+    if stime >= row.stime {
+        return Ok(false);
+    }
+    if local == 3 && row.status != 2 {
+        db.execute("
+            update notes
+                set status = 2,
+                    mtime = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+                where uuid = ?
+                  and hash <> ?
+                  and status in (1, 4, 5, 6)
+            ",
+            params![row.uuid, target])?;
     }
+    db.execute("
+        update notes
+            set status = ?,
+                buried_until = ?,
+                stime = ?
+            where hash = ?
+        ",
+        params![row.status, row.buried_until, row.stime, target])?;
+    Ok(true)
+}
+
+// The version itself and all its ancestors known to the DB.
+fn ancestors(db: &Connection, hash: &str) -> Result<Vec<String>> {
+    let mut q = db.prepare("
+        with recursive ancestor(hash) as (
+            select ?
+          union
+            select n.parent
+            from notes n, ancestor a
+            where n.hash = a.hash
+              and n.parent is not null
+        )
+        select hash from ancestor
+    ")?;
+    let res = q.query_map([hash], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(res)
+}
+
+// Status of the version, none if the DB doesn't have it.
+pub fn note_status(db: &Connection, hash: &str) -> Result<Option<i64>> {
+    let status = db.query_row(
+        "select status from notes where hash = ?",
+        [hash],
+        |row| row.get(0)).optional()?;
+    Ok(status)
 }
 
 // insert_notes must be idempotent (loading the same file again changes nothing).
 // So when loading notes from a file it is ok to stop on the first error,
 // fix that error and try to load the updated file again.
 // We use hash(tags, note_data) to accomplish this.
+// Notes are inserted in a single transaction, so a failed batch changes
+// nothing.
+// Returns the number of notes that conflict with their current versions.
 pub fn insert_notes(
-    db: &sqlite::Connection,
+    db: &Connection,
     notes: &[DbNote]
-) -> Result<()> {
-    let mut insert_note = InsertNoteQuery::init(db)?;
-
+) -> Result<usize> {
+    let tx = db.unchecked_transaction()?;
+    let mut conflicts = 0;
     for n in notes.iter() {
-        insert_note.exec(n)?;
+        if insert_note(&tx, n)? == Inserted::Conflict {
+            conflicts += 1;
+        }
     }
-    Ok(())
+    tx.commit()?;
+    Ok(conflicts)
+}
+
+pub fn insert_note(db: &Connection, note: &DbNote) -> Result<Inserted> {
+    let (hash, json) = note.hash_and_json();
+    insert_note_row(db, &NoteRow {
+        hash,
+        uuid: note.uuid.to_string(),
+        ctime: note.ctime.to_rfc3339(),
+        mtime: None,
+        tags: note.tags.clone(),
+        data: json,
+        parent: note.parent.clone(),
+        status: 1,
+        buried_until: None,
+        stime: None,
+    })
 }
 
 pub fn select_notes_for_review(
-    db: &sqlite::Connection,
-    tags: &[String]
+    db: &Connection,
+    query: Option<&Query>,
+    limit: usize
 ) -> Result<Vec<DbNote>> {
-    // FIXME: switch to rusqlite library and use regexp here
-    //     " and tags regexp ('\\b' || ? || '\\b')"
-    // see example at https://docs.rs/rusqlite/latest/rusqlite/functions/
-    let tag_filter = vec![" and tags like ('%' || ? || '%')"; tags.len()].join("");
+    let (filter, params) = query_filter(query);
     let mut q = db.prepare(
-        format!("
+        &format!("
             select
                 n.uuid, n.ctime, n.tags, n.data
             from queue q, notes n
@@ -137,92 +335,740 @@ pub fn select_notes_for_review(
               and q.next_review <= strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
               and q.note_id = n.uuid
               and n.status = 1
-              {tag_filter}
+              {filter}
             order by random()
-            limit 10")
+            limit {limit}")
     )?;
 
-    for (i, tag) in tags.iter().enumerate() {
-        q.bind(i+1, tag.as_str())?;
+    let mut rows = q.query(rusqlite::params_from_iter(params))?;
+    let mut res = Vec::new();
+    while let Some(row) = rows.next()? {
+        res.push(db_note_from_row(row)?);
+    }
+
+    Ok(res)
+}
+
+// Number of notes matching the query that are due for review now.
+pub fn count_due_notes(
+    db: &Connection,
+    query: Option<&Query>
+) -> Result<usize> {
+    let (filter, params) = query_filter(query);
+    let n = db.query_row(
+        &format!("
+            select count(*)
+            from queue q, notes n
+            where true
+              and q.next_review <= strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+              and q.note_id = n.uuid
+              and n.status = 1
+              {filter}"),
+        rusqlite::params_from_iter(params),
+        |row| row.get(0))?;
+    Ok(n)
+}
+
+// Condition to append to a `where` clause over `notes n` and its parameters.
+// Tags are matched as a whole: reviewing `go` does not select notes tagged
+// with `golang`.
+fn query_filter(query: Option<&Query>) -> (String, Vec<String>) {
+    let mut params = Vec::new();
+    let filter = match query {
+        Some(q) => format!("and {}", q.to_sql(&mut params)),
+        None => String::new(),
+    };
+    (filter, params)
+}
+
+#[cfg_attr(test, derive(PartialEq, Debug))]
+pub struct TagStats {
+    pub tag: String,
+    pub notes: usize,
+    pub due: usize,
+}
+
+// Number of current notes and notes due for review for every tag.
+// Tags are hierarchical (`lang/rust/traits`), and a note is counted for all
+// the ancestors of its tags as well.
+pub fn tag_stats(
+    db: &Connection
+) -> Result<Vec<TagStats>> {
+    let mut q = db.prepare("
+        with recursive node(note_hash, tag, due) as (
+            select
+                t.note_hash,
+                t.tag,
+                q.next_review <= strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+            from note_tags t, notes n, queue q
+            where t.note_hash = n.hash
+              and n.status = 1
+              and q.note_id = n.uuid
+          union
+            -- strip the last segment of the tag
+            select
+                note_hash,
+                rtrim(rtrim(tag, replace(tag, '/', '')), '/'),
+                due
+            from node
+            where instr(tag, '/') > 0
+        )
+        select
+            tag,
+            count(distinct note_hash),
+            count(distinct case when due then note_hash end)
+        from node
+        group by tag
+    ")?;
+
+    let mut res = q
+        .query_map([], |row| Ok(TagStats {
+            tag: row.get(0)?,
+            notes: row.get(1)?,
+            due: row.get(2)?,
+        }))?
+        .collect::<Result<Vec<_>, _>>()?;
+    // Sort by segments to keep children right after their parent.
+    res.sort_by(|a, b| a.tag.split('/').cmp(b.tag.split('/')));
+    Ok(res)
+}
+
+// Scheduling state of a note.
+// For a note that was never reviewed `last_review` is the time when it was
+// put into the queue, so the expected interval is zero.
+pub struct QueueEntry {
+    pub last_review: DateTime<Utc>,
+    pub next_review: DateTime<Utc>,
+}
+
+pub fn queue_entry(
+    db: &Connection,
+    note_id: &Uuid
+) -> Result<QueueEntry> {
+    let entry = db.query_row("
+        select
+            q.next_review,
+            coalesce(
+                (select max(r.ctime) from review r where r.note_id = q.note_id),
+                q.next_review)
+        from queue q
+        where q.note_id = ?
+        ",
+        [note_id.to_string()],
+        |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    ).optional()?;
+
+    match entry {
+        Some((next_review, last_review)) => Ok(QueueEntry {
+            next_review: from_db_time(&next_review)?,
+            last_review: from_db_time(&last_review)?,
+        }),
+        None =>
+            Err(anyhow!("Note {} is not in the review queue", note_id)),
     }
+}
+
+// Reviews of a note ordered by time.
+pub fn review_history(
+    db: &Connection,
+    note_id: &Uuid
+) -> Result<Vec<Review>> {
+    let mut q = db.prepare("
+        select r.ctime, rr.label
+        from review r, review_result rr
+        where r.note_id = ?
+          and rr.id = r.result
+        order by r.ctime asc, r.id asc
+    ")?;
+
+    let mut rows = q.query([note_id.to_string()])?;
+    let mut res = Vec::new();
+    while let Some(row) = rows.next()? {
+        res.push(Review {
+            ctime: from_db_time(&row.get::<_, String>(0)?)?,
+            result: ReviewResult::from_label(&row.get::<_, String>(1)?)?,
+        });
+    }
+    Ok(res)
+}
+
+// Number of reviews made since the given time.
+// If `policy` is set, only reviews of notes scheduled by that policy count.
+pub fn count_reviews_since(
+    db: &Connection,
+    since: &DateTime<Utc>,
+    policy: Option<&str>
+) -> Result<usize> {
+    let n = db.query_row("
+        select count(*)
+        from review
+        where ctime >= ?1
+          and (?2 is null or json_extract(decision, '$.policy') = ?2)
+        ",
+        params![to_db_time(since), policy],
+        |row| row.get(0))?;
+    Ok(n)
+}
+
+// Labels from the `review_result` dictionary ordered from the worst result
+// to the best one.
+pub fn review_results(db: &Connection) -> Result<Vec<String>> {
+    let mut q = db.prepare("select label from review_result order by id")?;
+    let res = q.query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(res)
+}
+
+// What was done since some moment of time.
+// Review duration is saved in `review.decision` by `fhmp review`.
+pub struct ReviewStats {
+    pub reviews: usize,
+    // Notes reviewed for the first time.
+    pub new_notes: usize,
+    pub seconds: i64,
+}
+
+pub fn review_stats_since(
+    db: &Connection,
+    since: &DateTime<Utc>
+) -> Result<ReviewStats> {
+    let stats = db.query_row("
+        select
+            count(*),
+            count(distinct r.note_id) filter (where not exists (
+                select 1 from review p
+                where p.note_id = r.note_id and p.ctime < ?1)),
+            coalesce(sum(json_extract(r.decision, '$.duration')), 0)
+        from review r
+        where r.ctime >= ?1
+        ",
+        [to_db_time(since)],
+        |row| Ok(ReviewStats {
+            reviews: row.get(0)?,
+            new_notes: row.get(1)?,
+            seconds: row.get(2)?,
+        }))?;
+    Ok(stats)
+}
+
+// Saves review outcome and schedules the next review of the note.
+// Both happen in a single transaction so the queue never gets out of sync
+// with the review history.
+pub fn save_review(
+    db: &Connection,
+    note_id: &Uuid,
+    ctime: &DateTime<Utc>,
+    result: &str,
+    decision: &serde_json::Value,
+    next_review: &DateTime<Utc>
+) -> Result<()> {
+    let tx = db.unchecked_transaction()?;
+    let note_id = note_id.to_string();
+    tx.execute("
+        insert into review
+          (note_id, ctime, result, decision)
+        values
+          (?, ?, (select id from review_result where label = ?), ?)
+        ",
+        params![note_id, to_db_time(ctime), result, decision.to_string()])?;
+    tx.execute("
+        update queue
+            set next_review = ?
+            where note_id = ?
+        ",
+        params![to_db_time(next_review), note_id])?;
+    tx.commit()?;
+    Ok(())
+}
+
+// Saves a review made on another device unless it is already known.
+// Reviews are identified by note, time and result.
+// Returns true if the review was saved.
+pub fn insert_review(
+    db: &Connection,
+    note_id: &Uuid,
+    ctime: &DateTime<Utc>,
+    result: &str,
+    decision: &serde_json::Value
+) -> Result<bool> {
+    let n = db.execute("
+        insert into review
+          (note_id, ctime, result, decision)
+        select ?1, ?2, rr.id, ?4
+        from review_result rr
+        where rr.label = ?3
+          and not exists (
+            select 1 from review r
+            where r.note_id = ?1 and r.ctime = ?2 and r.result = rr.id)
+        ",
+        params![note_id.to_string(), to_db_time(ctime), result, decision.to_string()])?;
+    Ok(n > 0)
+}
+
+// Moves the note in the queue without saving a review,
+// e.g. when the schedule was computed by another device.
+pub fn set_next_review(
+    db: &Connection,
+    note_id: &Uuid,
+    next_review: &DateTime<Utc>
+) -> Result<()> {
+    db.execute("
+        update queue
+            set next_review = ?
+            where note_id = ?
+        ",
+        params![to_db_time(next_review), note_id.to_string()])?;
+    Ok(())
+}
+
+// Current version of the note, if any. It may be suspended or buried.
+pub fn current_note(
+    db: &Connection,
+    note_id: &Uuid
+) -> Result<Option<DbNote>> {
+    let mut q = db.prepare("
+        select
+            n.uuid, n.ctime, n.tags, n.data
+        from notes n
+        where n.uuid = ?
+          and n.status in (1, 5, 6)
+    ")?;
+
+    let mut rows = q.query([note_id.to_string()])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(db_note_from_row(row)?)),
+        None => Ok(None),
+    }
+}
+
+// Active notes with UUIDs starting with the prefix.
+pub fn notes_by_uuid_prefix(db: &Connection, prefix: &str) -> Result<Vec<DbNote>> {
+    let mut q = db.prepare("
+        select
+            n.uuid, n.ctime, n.tags, n.data
+        from notes n
+        where n.status = 1
+          and n.uuid like ? || '%'
+        order by n.ctime asc
+    ")?;
+
+    let mut rows = q.query([prefix.to_lowercase()])?;
+    let mut res = Vec::new();
+    while let Some(row) = rows.next()? {
+        res.push(db_note_from_row(row)?);
+    }
+    Ok(res)
+}
+
+// A version of a note with its metadata.
+pub struct NoteVersion {
+    pub hash: String,
+    // When the version was retired.
+    pub mtime: Option<String>,
+    pub parent: Option<String>,
+    // Label of the status, e.g. 'active'.
+    pub status: String,
+    pub note: DbNote,
+}
 
+// All versions of the note in the order they were added.
+pub fn note_versions(db: &Connection, note_id: &Uuid) -> Result<Vec<NoteVersion>> {
+    let mut q = db.prepare("
+        select
+            n.uuid, n.ctime, n.tags, n.data, n.hash, n.mtime, n.parent, s.label
+        from notes n, note_status s
+        where n.uuid = ?
+          and s.id = n.status
+        order by n.rowid
+    ")?;
+
+    let mut rows = q.query([note_id.to_string()])?;
     let mut res = Vec::new();
-    while let sqlite::State::Row = q.next()? {
-        res.push(db_note_from_row(&q)?);
+    while let Some(row) = rows.next()? {
+        res.push(NoteVersion {
+            hash: row.get(4)?,
+            mtime: row.get(5)?,
+            parent: row.get(6)?,
+            status: row.get(7)?,
+            note: db_note_from_row(row)?,
+        });
     }
+    Ok(res)
+}
 
+// UUIDs of notes in any status starting with the prefix.
+pub fn uuids_by_prefix(db: &Connection, prefix: &str) -> Result<Vec<Uuid>> {
+    let mut q = db.prepare("
+        select distinct uuid from notes where uuid like ? || '%' order by uuid
+    ")?;
+    let res = q.query_map([prefix.to_lowercase()], |row| row.get::<_, String>(0))?
+        .map(|uuid| Ok(Uuid::parse_str(&uuid?)?))
+        .collect::<Result<_>>()?;
     Ok(res)
 }
 
+// The note with the UUID prefix, it must be unique.
+pub fn find_uuid(db: &Connection, prefix: &str) -> Result<Uuid> {
+    match uuids_by_prefix(db, prefix)?.as_slice() {
+        [uuid] => Ok(*uuid),
+        [] => Err(anyhow!("No note with UUID {}", prefix)),
+        uuids => Err(anyhow!("UUID prefix {} matches {} notes", prefix, uuids.len())),
+    }
+}
+
+// Makes the data of an older version current again. It is saved as a new
+// version based on the current one, so it replaces the current version
+// everywhere on sync like an edit. Conflicting versions are kept.
+pub fn revert_note(
+    db: &Connection,
+    note_id: &Uuid,
+    hash: &str
+) -> Result<()> {
+    let tx = db.unchecked_transaction()?;
+    let old = tx.query_row("
+        select ctime, tags, data from notes
+        where uuid = ? and hash = ?
+        ",
+        params![note_id.to_string(), hash],
+        |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))
+        .optional()?;
+    let Some((ctime, tags, data)) = old else {
+        return Err(anyhow!("Note {} has no version {}", note_id, hash));
+    };
+    let current: Option<String> = tx.query_row("
+        select hash from notes
+        where uuid = ? and status in (1, 4, 5, 6)
+        ",
+        [note_id.to_string()],
+        |row| row.get(0)).optional()?;
+    let new_hash = revert_hash(hash, current.as_deref());
+    insert_note_row(&tx, &NoteRow {
+        hash: new_hash.clone(),
+        uuid: note_id.to_string(),
+        ctime,
+        mtime: None,
+        tags,
+        data,
+        parent: current,
+        status: 1,
+        buried_until: None,
+        stime: None,
+    })?;
+    // The new version of a deleted note is deleted, revert brings it back.
+    tx.execute(
+        "update notes set status = 1 where hash = ? and status = 4",
+        [new_hash])?;
+    tx.commit()?;
+    Ok(())
+}
+
+// Hashes of versions are hashes of their data, the reverted version has the
+// data of the older one. Its hash is made from the hashes of both versions.
+fn revert_hash(hash: &str, current: Option<&str>) -> String {
+    let mut hasher = Shake128::default();
+    hasher.update(b"revert");
+    hasher.update(hash.as_bytes());
+    hasher.update(current.unwrap_or_default().as_bytes());
+    let mut res = [0u8; 16];
+    hasher.finalize_xof().read(&mut res);
+    hex::encode(res)
+}
+
+// Hash of the current version. It differs from the hash of its data if the
+// note was reverted.
+pub fn current_hash(db: &Connection, note_id: &Uuid) -> Result<Option<String>> {
+    let hash = db.query_row("
+        select hash from notes
+        where uuid = ? and status in (1, 5, 6)
+        ",
+        [note_id.to_string()],
+        |row| row.get(0)).optional()?;
+    Ok(hash)
+}
+
+// Replaces the current version, the version keeps its suspended, buried or
+// deleted status.
+fn make_current(db: &Connection, note_id: &Uuid, hash: &str) -> Result<()> {
+    let (status, buried_until): (i64, Option<String>) = db.query_row("
+        select status, buried_until from notes
+        where uuid = ? and status in (1, 4, 5, 6)
+        union all
+        select 1, null
+        limit 1
+        ",
+        [note_id.to_string()],
+        |row| Ok((row.get(0)?, row.get(1)?)))?;
+    db.execute("
+        update notes
+            set status = 2,
+                mtime = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+            where uuid = ?
+              and hash <> ?
+              and status in (1, 4, 5, 6)
+        ",
+        params![note_id.to_string(), hash])?;
+    let n = db.execute("
+        update notes
+            set status = ?,
+                buried_until = ?,
+                mtime = null
+            where uuid = ? and hash = ?
+        ",
+        params![status, buried_until, note_id.to_string(), hash])?;
+    if n != 1 {
+        return Err(anyhow!("Note {} has no version {}", note_id, hash));
+    }
+    Ok(())
+}
+
+// States of a note set by the user. Inactive notes are not reviewed.
+pub enum NoteStatus {
+    Active,
+    Deleted,
+    Suspended,
+    Buried(DateTime<Utc>),
+}
+
+// Changes the status of the current version.
+// Returns false if the note has no current version, e.g. it was deleted.
+pub fn set_note_status(
+    db: &Connection,
+    note_id: &Uuid,
+    status: &NoteStatus
+) -> Result<bool> {
+    let (id, buried_until) = match status {
+        NoteStatus::Active => (1, None),
+        NoteStatus::Deleted => (4, None),
+        NoteStatus::Suspended => (5, None),
+        NoteStatus::Buried(until) => (6, Some(to_db_time(until))),
+    };
+    let n = db.execute("
+        update notes
+            set status = ?1,
+                buried_until = ?2,
+                mtime = case when ?1 = 4
+                    then strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+                    else mtime end
+            where uuid = ?3
+              and status in (1, 5, 6)
+        ",
+        params![id, buried_until, note_id.to_string()])?;
+    Ok(n > 0)
+}
+
+// Notes buried until a past time become active again.
+pub fn unbury_notes(db: &Connection) -> Result<usize> {
+    let n = db.execute("
+        update notes
+            set status = 1,
+                buried_until = null,
+                -- The same on every device, it is not a change to sync.
+                stime = strftime('%Y-%m-%dT%H:%M:%fZ', buried_until)
+            where status = 6
+              and buried_until <= strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+        ",
+        [])?;
+    Ok(n)
+}
+
+// Whether the DB has any version of the note, in any status.
+pub fn note_exists(db: &Connection, note_id: &Uuid) -> Result<bool> {
+    let exists = db.query_row(
+        "select exists (select 1 from notes where uuid = ?)",
+        [note_id.to_string()],
+        |row| row.get(0))?;
+    Ok(exists)
+}
+
+// Versions in the 'conflict' status ordered by note.
+pub fn conflicting_notes(db: &Connection) -> Result<Vec<DbNote>> {
+    let mut q = db.prepare("
+        select
+            n.uuid, n.ctime, n.tags, n.data
+        from notes n
+        where n.status = 3
+        order by n.uuid, n.rowid
+    ")?;
+
+    let mut rows = q.query([])?;
+    let mut res = Vec::new();
+    while let Some(row) = rows.next()? {
+        res.push(db_note_from_row(row)?);
+    }
+    Ok(res)
+}
+
+// Makes the chosen version current and retires all the others.
+pub fn resolve_conflict(
+    db: &Connection,
+    note_id: &Uuid,
+    hash: &str
+) -> Result<()> {
+    let tx = db.unchecked_transaction()?;
+    make_current(&tx, note_id, hash)?;
+    tx.execute("
+        update notes
+            set status = 2,
+                mtime = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+            where uuid = ?
+              and hash <> ?
+              and status = 3
+        ",
+        params![note_id.to_string(), hash])?;
+    tx.commit()?;
+    Ok(())
+}
+
 pub fn active_notes(
-    db: &sqlite::Connection
-) -> Result<DbNotes> {
+    db: &Connection
+) -> Result<DbNotes<'_>> {
     let q = db.prepare("
         select
-            uuid, ctime, tags, data
+            uuid, ctime, tags, data, hash
         from notes
         where status = 1
         order by ctime asc
     ")?;
-    Ok(DbNotes(q))
+    Ok(DbNotes { q, params: Vec::new(), parents: false })
+}
+
+// Active notes matching the query.
+pub fn matching_notes<'a>(
+    db: &'a Connection,
+    query: Option<&Query>
+) -> Result<DbNotes<'a>> {
+    let (filter, params) = query_filter(query);
+    let q = db.prepare(&format!("
+        select
+            n.uuid, n.ctime, n.tags, n.data, n.hash
+        from notes n
+        where n.status = 1
+          {filter}
+        order by n.ctime asc
+    "))?;
+    Ok(DbNotes { q, params, parents: false })
+}
+
+// Notes are read from the DB one at a time while iterating.
+pub struct DbNotes<'a> {
+    q: Statement<'a>,
+    params: Vec<String>,
+    parents: bool,
+}
+
+impl DbNotes<'_> {
+    // Notes are based on their stored versions, so that edited notes are
+    // added as new versions of them.
+    pub fn with_parents(self) -> Self {
+        DbNotes { parents: true, ..self }
+    }
+
+    pub fn iter(&mut self) -> Result<impl Iterator<Item = Result<DbNote>> + '_> {
+        let parents = self.parents;
+        let rows = self.q.query(rusqlite::params_from_iter(&self.params))?;
+        Ok(rows.and_then(move |row| Ok(DbNote {
+            parent: if parents { Some(row.get(4)?) } else { None },
+            ..db_note_from_row(row)?
+        })))
+    }
+}
+
+pub struct SearchResult {
+    pub note: DbNote,
+    // Label of the status, e.g. 'suspended'.
+    pub status: String,
+    // Matching fragment of the text or tags, matches are enclosed in
+    // the given markers.
+    pub snippet: String,
+}
+
+// Current notes matching all words of the query, the best matches first.
+// A word ending with `*` matches words starting with it.
+pub fn search_notes(
+    db: &Connection,
+    query: &str,
+    markers: (&str, &str),
+    limit: usize
+) -> Result<Vec<SearchResult>> {
+    let mut q = db.prepare("
+        select
+            n.uuid, n.ctime, n.tags, n.data, s.label,
+            snippet(notes_fts, -1, ?, ?, '…', 12)
+        from notes_fts f, notes n, note_status s
+        where notes_fts match ?
+          and n.hash = f.hash
+          and s.id = n.status
+          and n.status in (1, 5, 6)
+        order by f.rank
+        limit ?
+    ")?;
+
+    let mut rows = q.query(params![markers.0, markers.1, fts_query(query), limit])?;
+    let mut res = Vec::new();
+    while let Some(row) = rows.next()? {
+        res.push(SearchResult {
+            note: db_note_from_row(row)?,
+            status: row.get(4)?,
+            snippet: row.get(5)?,
+        });
+    }
+    Ok(res)
+}
+
+// Words are quoted, so punctuation is not taken for FTS5 syntax.
+fn fts_query(query: &str) -> String {
+    query.split_whitespace()
+        .map(|w| match w.strip_suffix('*') {
+            Some(prefix) => format!("\"{}\"*", prefix.replace('"', "\"\"")),
+            None => format!("\"{}\"", w.replace('"', "\"\"")),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 // Assumes that q starts like "select uuid, ctime, tags, data ..".
-fn db_note_from_row(q: &sqlite::Statement) -> Result<DbNote> {
+fn db_note_from_row(row: &rusqlite::Row) -> Result<DbNote> {
     Ok(DbNote {
         uuid:
-            Uuid::parse_str(q.read::<String>(0)?.as_str())?,
+            Uuid::parse_str(row.get::<_, String>(0)?.as_str())?,
         ctime:
-            DateTime::parse_from_rfc3339(q.read::<String>(1)?.as_str())?
-                .with_timezone(&Utc),
+            from_db_time(row.get::<_, String>(1)?.as_str())?,
         tags:
-            q.read::<String>(2)?,
+            row.get::<_, String>(2)?,
         data:
-            serde_json::from_str(q.read::<String>(3)?.as_str())?,
+            serde_json::from_str(row.get::<_, String>(3)?.as_str())?,
+        parent: None,
     })
 }
 
-pub struct DbNotes<'a>(sqlite::Statement<'a>);
-
-impl<'a> Iterator for DbNotes<'a> {
-    type Item = DbNote;
-    fn next(&mut self) -> Option<DbNote> {
-        match self.0.next() {
-            Ok(sqlite::State::Row) => db_note_from_row(&self.0).ok(),
-            _ => None
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::note::*;
+    use crate::query;
     use chrono::{Local, Utc};
     use uuid::Uuid;
 
     #[test]
     fn can_init_schema() -> Result<()> {
-        let db = sqlite::open(":memory:")?;
+        let db = open(":memory:")?;
         init_schema(&db)
     }
 
     #[test]
     fn can_add_single_note() -> Result<()> {
-        let db = sqlite::open(":memory:")?;
+        let db = open(":memory:")?;
         init_schema(&db)?;
         let note = text_note("hello\nworld", "hello!");
-        insert_notes(&db, &vec![note.clone()])?;
+        insert_notes(&db, std::slice::from_ref(&note))?;
 
-        let mut iter = active_notes(&db)?;
-        assert_eq!(Some(note), iter.next());
-        assert_eq!(None, iter.next());
+        assert_eq!(active(&db)?, vec![note]);
         Ok(())
     }
 
     #[test]
     fn insert_skips_duplicates() -> Result<()> {
-        let db = sqlite::open(":memory:")?;
+        let db = open(":memory:")?;
         init_schema(&db)?;
         let note1 = text_note("hello\nworld", "hello!");
         let note2 = text_note("bye\nworld", "bye!");
@@ -233,7 +1079,7 @@ mod tests {
 
         let mut n1 = 0;
         let mut n2 = 0;
-        for n in active_notes(&db)? {
+        for n in active(&db)? {
             if n == note1 { n1 += 1 }
             else if n == note2 { n2 += 1 }
         }
@@ -242,9 +1088,26 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn failed_batch_is_rolled_back() -> Result<()> {
+        let db = open(":memory:")?;
+        init_schema(&db)?;
+        db.execute_batch("
+            create temp trigger fail_insert before insert on notes
+                when new.data like '%boom%'
+                begin select raise(abort, 'boom'); end;
+        ")?;
+        let notes = [text_note("a", "fine"), text_note("a", "boom")];
+        assert!(insert_notes(&db, &notes).is_err());
+        assert_eq!(active(&db)?.len(), 0);
+        let tags: i64 = db.query_row("select count(*) from note_tags", [], |r| r.get(0))?;
+        assert_eq!(tags, 0);
+        Ok(())
+    }
+
     #[test]
     fn can_update_note() -> Result<()> {
-        let db = sqlite::open(":memory:")?;
+        let db = open(":memory:")?;
         init_schema(&db)?;
         let note1 = text_note("hello\nworld", "hello!");
         let note2 = DbNote {
@@ -254,15 +1117,48 @@ mod tests {
         let notes = vec![note1, note2.clone()];
         insert_notes(&db, &notes)?;
 
-        let mut iter = active_notes(&db)?;
-        assert_eq!(Some(note2), iter.next());
-        assert_eq!(None, iter.next());
+        assert_eq!(active(&db)?, vec![note2.clone()]);
+
+        // Loading the old version again changes nothing.
+        insert_notes(&db, &notes[..1])?;
+        assert_eq!(current_note(&db, &note2.uuid)?, Some(note2));
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_edits_conflict() -> Result<()> {
+        let db = open(":memory:")?;
+        init_schema(&db)?;
+        let hash = |n: &DbNote| n.hash_and_json().0;
+        let v1 = text_note("math", "v1");
+        let v2 = DbNote { uuid: v1.uuid, parent: Some(hash(&v1)), ..text_note("math", "v2") };
+        // Edited on another device, also based on v1.
+        let v3 = DbNote { uuid: v1.uuid, parent: Some(hash(&v1)), ..text_note("math", "v3") };
+        assert_eq!(insert_notes(&db, &[v1.clone(), v2.clone()])?, 0);
+        assert_eq!(insert_notes(&db, std::slice::from_ref(&v3))?, 1);
+        let current = |n: &DbNote| current_note(&db, &n.uuid)
+            .map(|c| c.map(|c| hash(&c)));
+        assert_eq!(current(&v1)?, Some(hash(&v2)));
+        assert_eq!(conflicting_notes(&db)?.len(), 1);
+
+        // A version based on the conflicting one resolves the conflict.
+        let v4 = DbNote { uuid: v1.uuid, parent: Some(hash(&v3)), ..text_note("math", "v4") };
+        assert_eq!(insert_notes(&db, std::slice::from_ref(&v4))?, 0);
+        assert_eq!(current(&v1)?, Some(hash(&v4)));
+        assert!(conflicting_notes(&db)?.is_empty());
+
+        // Choosing one of the versions.
+        let v5 = DbNote { uuid: v1.uuid, parent: Some(hash(&v2)), ..text_note("math", "v5") };
+        assert_eq!(insert_notes(&db, std::slice::from_ref(&v5))?, 1);
+        resolve_conflict(&db, &v1.uuid, &hash(&v5))?;
+        assert_eq!(current(&v1)?, Some(hash(&v5)));
+        assert!(conflicting_notes(&db)?.is_empty());
         Ok(())
     }
 
     #[test]
     fn insert_adds_to_queue() -> Result<()> {
-        let db = sqlite::open(":memory:")?;
+        let db = open(":memory:")?;
         init_schema(&db)?;
         let note1 = text_note("hello\nworld", "hello!");
         let note2 = text_note("bye\nworld", "bye!");
@@ -271,7 +1167,7 @@ mod tests {
 
         let mut n1 = 0;
         let mut n2 = 0;
-        for n in select_notes_for_review(&db)?.iter() {
+        for n in select_notes_for_review(&db, None, 10)?.iter() {
             if *n == note1 { n1 += 1 }
             else if *n == note2 { n2 += 1 }
         }
@@ -280,13 +1176,356 @@ mod tests {
         Ok(())
     }
 
-    // helper function for tests
+    #[test]
+    fn save_review_reschedules_note() -> Result<()> {
+        let db = open(":memory:")?;
+        init_schema(&db)?;
+        let note = text_note("hello", "hello!");
+        insert_notes(&db, std::slice::from_ref(&note))?;
+
+        let entry = queue_entry(&db, &note.uuid)?;
+        assert_eq!(entry.last_review, entry.next_review);
+
+        let next_review = Utc::now() + chrono::Duration::days(1);
+        let decision = serde_json::json!({"new_interval": 86400});
+        save_review(&db, &note.uuid, &Utc::now(), "easy", &decision, &next_review)?;
+
+        assert!(select_notes_for_review(&db, None, 10)?.is_empty());
+        let entry = queue_entry(&db, &note.uuid)?;
+        assert_eq!(to_db_time(&entry.next_review), to_db_time(&next_review));
+        assert!(entry.last_review < entry.next_review);
+
+        let today = Utc::now() - chrono::Duration::hours(1);
+        assert_eq!(count_reviews_since(&db, &today, None)?, 1);
+        assert_eq!(count_reviews_since(&db, &today, Some("5min"))?, 0);
+
+        let stats = review_stats_since(&db, &today)?;
+        assert_eq!((stats.reviews, stats.new_notes, stats.seconds), (1, 1, 0));
+
+        let history = review_history(&db, &note.uuid)?;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].result, ReviewResult::Easy);
+        Ok(())
+    }
+
+    #[test]
+    fn review_stats_count_new_notes() -> Result<()> {
+        let db = open(":memory:")?;
+        init_schema(&db)?;
+        let note1 = text_note("hello", "hello!");
+        let note2 = text_note("bye", "bye!");
+        insert_notes(&db, &[note1.clone(), note2.clone()])?;
+
+        let now = Utc::now();
+        let today = now - chrono::Duration::hours(1);
+        let yesterday = now - chrono::Duration::days(1);
+        let decision = serde_json::json!({"duration": 20});
+        save_review(&db, &note1.uuid, &yesterday, "easy", &decision, &now)?;
+        save_review(&db, &note1.uuid, &now, "easy", &decision, &now)?;
+        save_review(&db, &note2.uuid, &now, "hard", &decision, &now)?;
+        save_review(&db, &note2.uuid, &now, "easy", &decision, &now)?;
+
+        let stats = review_stats_since(&db, &today)?;
+        assert_eq!((stats.reviews, stats.new_notes, stats.seconds), (3, 1, 60));
+        Ok(())
+    }
+
+    #[test]
+    fn review_matches_whole_tags() -> Result<()> {
+        let db = open(":memory:")?;
+        init_schema(&db)?;
+        let go = text_note("go\nlang", "go");
+        let golang = text_note("algorithms\ngolang", "golang");
+        insert_notes(&db, &[go.clone(), golang])?;
+
+        let q = query::parse("go")?;
+        assert_eq!(select_notes_for_review(&db, Some(&q), 10)?, vec![go.clone()]);
+        assert_eq!(count_due_notes(&db, Some(&q))?, 1);
+
+        let q = query::parse("Lang go")?;
+        assert_eq!(select_notes_for_review(&db, Some(&q), 10)?, vec![go.clone()]);
+
+        let q = query::parse("g.*")?;
+        assert!(select_notes_for_review(&db, Some(&q), 10)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn review_filters_by_query() -> Result<()> {
+        let db = open(":memory:")?;
+        init_schema(&db)?;
+        let go = text_note("go\nlang", "go");
+        let golang = text_note("algorithms\ngolang", "golang");
+        insert_notes(&db, &[go.clone(), golang.clone()])?;
+
+        // A tag is not a prefix of other tags, only of its subtags.
+        let q = query::parse("g")?;
+        assert!(select_notes_for_review(&db, Some(&q), 10)?.is_empty());
+
+        let q = query::parse("!go")?;
+        assert_eq!(select_notes_for_review(&db, Some(&q), 10)?, vec![golang.clone()]);
+        assert_eq!(count_due_notes(&db, Some(&q))?, 1);
+
+        let q = query::parse("go | algorithms")?;
+        assert_eq!(count_due_notes(&db, Some(&q))?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn sql_regexp_matches_tags() -> Result<()> {
+        let db = open(":memory:")?;
+        init_schema(&db)?;
+        insert_notes(&db, &[text_note("go\nlang", "go"), text_note("golang", "golang")])?;
+
+        let count = |re: &str| -> Result<i64> {
+            Ok(db.query_row("select count(*) from notes where tags regexp ?", [re], |r| r.get(0))?)
+        };
+        assert_eq!(count("(?m)^go$")?, 1);
+        assert_eq!(count("go")?, 2);
+        assert!(count("(").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn select_notes_by_query() -> Result<()> {
+        let db = open(":memory:")?;
+        init_schema(&db)?;
+        let rust = text_note("lang\nrust", "rust");
+        let async_rust = text_note("async\nlang\nrust", "async");
+        let math = text_note("5min\nmath", "math");
+        let slow_math = text_note("20min\nmath", "slow math");
+        insert_notes(&db, &[
+            rust.clone(), async_rust.clone(), math.clone(), slow_math.clone()
+        ])?;
+
+        let q = query::parse("rust & !async | (math & 5min)")?;
+        let notes = matching(&db, Some(&q))?;
+        assert_eq!(notes, vec![rust, math]);
+        assert_eq!(count_due_notes(&db, Some(&q))?, 2);
+        assert_eq!(matching(&db, None)?.len(), 4);
+        Ok(())
+    }
+
+    fn stats(tag: &str, notes: usize, due: usize) -> TagStats {
+        TagStats { tag: tag.to_string(), notes, due }
+    }
+
+    #[test]
+    fn versions_and_revert() -> Result<()> {
+        let db = open(":memory:")?;
+        init_schema(&db)?;
+        let v1 = text_note("a", "v1");
+        let v2 = DbNote { uuid: v1.uuid, ..text_note("a", "v2") };
+        insert_notes(&db, &[v1.clone(), v2.clone()])?;
+
+        let versions = note_versions(&db, &v1.uuid)?;
+        let status: Vec<_> = versions.iter().map(|v| v.status.as_str()).collect();
+        assert_eq!(status, ["retired", "active"]);
+        assert!(versions[0].mtime.is_some());
+        assert_eq!(versions[1].parent, Some(versions[0].hash.clone()));
+        assert_eq!(uuids_by_prefix(&db, &v1.uuid.to_string()[..6])?, vec![v1.uuid]);
+
+        revert_note(&db, &v1.uuid, &versions[0].hash)?;
+        assert_eq!(current_note(&db, &v1.uuid)?, Some(v1.clone()));
+        let versions = note_versions(&db, &v1.uuid)?;
+        let status: Vec<_> = versions.iter().map(|v| v.status.as_str()).collect();
+        // The data of v1 is a new version based on v2.
+        assert_eq!(status, ["retired", "retired", "active"]);
+        assert_eq!(versions[2].parent, Some(versions[1].hash.clone()));
+        assert_ne!(versions[2].hash, versions[0].hash);
+        assert_eq!(current_hash(&db, &v1.uuid)?, Some(versions[2].hash.clone()));
+        let dumped = matching_notes(&db, None)?.with_parents().iter()?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(dumped, vec![DbNote { parent: Some(versions[2].hash.clone()), ..v1.clone() }]);
+        // An edit of the reverted version is not a conflict.
+        let v3 = DbNote { uuid: v1.uuid, parent: current_hash(&db, &v1.uuid)?, ..text_note("a", "v3") };
+        assert_eq!(insert_note(&db, &v3)?, Inserted::New);
+
+        assert!(revert_note(&db, &v1.uuid, "nosuchhash").is_err());
+        assert_eq!(note_versions(&db, &v1.uuid)?.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn select_by_uuid_prefix() -> Result<()> {
+        let db = open(":memory:")?;
+        init_schema(&db)?;
+        let note = text_note("a", "v1");
+        let v2 = DbNote { uuid: note.uuid, ..text_note("a", "v2") };
+        insert_notes(&db, &[note.clone(), v2.clone(), text_note("a", "other")])?;
+
+        let prefix = note.uuid.to_string()[..8].to_uppercase();
+        assert_eq!(notes_by_uuid_prefix(&db, &prefix)?, vec![v2]);
+        assert_eq!(notes_by_uuid_prefix(&db, "")?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn suspended_and_buried_notes_are_not_reviewed() -> Result<()> {
+        let db = open(":memory:")?;
+        init_schema(&db)?;
+        let suspended = text_note("a", "suspended");
+        let buried = text_note("a", "buried");
+        let other = text_note("a", "other");
+        insert_notes(&db, &[suspended.clone(), buried.clone(), other.clone()])?;
+
+        assert!(set_note_status(&db, &suspended.uuid, &NoteStatus::Suspended)?);
+        let tomorrow = Utc::now() + chrono::Duration::days(1);
+        assert!(set_note_status(&db, &buried.uuid, &NoteStatus::Buried(tomorrow))?);
+        assert_eq!(select_notes_for_review(&db, None, 10)?, vec![other.clone()]);
+        assert_eq!(active(&db)?, vec![other.clone()]);
+        assert_eq!(unbury_notes(&db)?, 0);
+
+        // A new version keeps the note suspended.
+        let v2 = DbNote { uuid: suspended.uuid, ..text_note("a", "suspended v2") };
+        insert_notes(&db, std::slice::from_ref(&v2))?;
+        assert_eq!(current_note(&db, &v2.uuid)?, Some(v2.clone()));
+        assert_eq!(active(&db)?.len(), 1);
+
+        let yesterday = Utc::now() - chrono::Duration::days(1);
+        set_note_status(&db, &buried.uuid, &NoteStatus::Buried(yesterday))?;
+        assert_eq!(unbury_notes(&db)?, 1);
+        set_note_status(&db, &v2.uuid, &NoteStatus::Active)?;
+        assert_eq!(select_notes_for_review(&db, None, 10)?.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn search_text_and_tags() -> Result<()> {
+        let db = open(":memory:")?;
+        init_schema(&db)?;
+        let card = DbNote {
+            data: NoteData::Card(vec![
+                "What does 'a mean?".to_string(),
+                "A lifetime parameter.".to_string(),
+            ]),
+            ..text_note("rust", "")
+        };
+        let text = text_note("rust\nrust/lifetimes", "Elision rules");
+        let other = text_note("math", "Lifetime of a star");
+        insert_notes(&db, &[card.clone(), text.clone(), other.clone()])?;
+
+        let found = search_notes(&db, "lifetimes rust", ("[", "]"), 10)?;
+        let notes: Vec<_> = found.iter().map(|r| r.note.clone()).collect();
+        assert_eq!(notes, vec![text.clone(), card.clone()]);
+        assert!(found[1].snippet.ends_with("A [lifetime] parameter."), "{}", found[1].snippet);
+
+        // Punctuation is not FTS5 syntax.
+        assert_eq!(search_notes(&db, "'a mean?\" OR", ("[", "]"), 10)?.len(), 0);
+        assert_eq!(search_notes(&db, "'a mean?", ("[", "]"), 10)?.len(), 1);
+        assert_eq!(search_notes(&db, "eli*", ("[", "]"), 10)?.len(), 1);
+
+        // Old versions are not found, suspended ones are.
+        let v2 = DbNote { uuid: other.uuid, ..text_note("math", "Life of a star") };
+        insert_notes(&db, std::slice::from_ref(&v2))?;
+        set_note_status(&db, &card.uuid, &NoteStatus::Suspended)?;
+        let found = search_notes(&db, "lifetime", ("[", "]"), 10)?;
+        let status: Vec<_> = found.iter().map(|r| r.status.as_str()).collect();
+        assert_eq!(status, ["active", "suspended"]);
+        Ok(())
+    }
+
+    #[test]
+    fn deleted_notes_are_reverted() -> Result<()> {
+        let db = open(":memory:")?;
+        init_schema(&db)?;
+        let note = text_note("a", "v1");
+        insert_notes(&db, std::slice::from_ref(&note))?;
+
+        assert!(set_note_status(&db, &note.uuid, &NoteStatus::Deleted)?);
+        assert!(!set_note_status(&db, &note.uuid, &NoteStatus::Suspended)?);
+        assert_eq!(current_note(&db, &note.uuid)?, None);
+        assert!(select_notes_for_review(&db, None, 10)?.is_empty());
+        let versions = note_versions(&db, &note.uuid)?;
+        assert_eq!(versions[0].status, "deleted");
+        assert!(versions[0].mtime.is_some());
+
+        // Adding the same or a new version doesn't bring it back.
+        insert_notes(&db, std::slice::from_ref(&note))?;
+        assert_eq!(current_note(&db, &note.uuid)?, None);
+        insert_notes(&db, &[DbNote { uuid: note.uuid, ..text_note("a", "v2") }])?;
+        assert_eq!(current_note(&db, &note.uuid)?, None);
+        let status: Vec<_> = note_versions(&db, &note.uuid)?.into_iter().map(|v| v.status).collect();
+        assert_eq!(status, ["retired", "deleted"]);
+        revert_note(&db, &note.uuid, &versions[0].hash)?;
+        assert_eq!(current_note(&db, &note.uuid)?, Some(note));
+        Ok(())
+    }
+
+    #[test]
+    fn tags_are_counted_for_current_notes() -> Result<()> {
+        let db = open(":memory:")?;
+        init_schema(&db)?;
+        let note1 = text_note("go\nlang", "go");
+        let note2 = DbNote {
+            uuid: note1.uuid,
+            ..text_note("go\nlanguage", "go!")
+        };
+        let note3 = text_note("lang", "rust");
+        insert_notes(&db, &[note1, note2, note3])?;
+
+        assert_eq!(tag_stats(&db)?, vec![
+            stats("go", 1, 1),
+            stats("lang", 1, 1),
+            stats("language", 1, 1),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn tag_stats_include_descendants() -> Result<()> {
+        let db = open(":memory:")?;
+        init_schema(&db)?;
+        let traits = text_note("lang/rust\nlang/rust/traits", "traits");
+        let macros = text_note("lang/rust/macros", "macros");
+        let haskell = text_note("lang/haskell\nlang-x", "haskell");
+        insert_notes(&db, &[traits.clone(), macros, haskell])?;
+        let later = Utc::now() + chrono::Duration::days(1);
+        let decision = serde_json::json!({});
+        save_review(&db, &traits.uuid, &Utc::now(), "easy", &decision, &later)?;
+
+        assert_eq!(tag_stats(&db)?, vec![
+            stats("lang", 3, 2),
+            stats("lang/haskell", 1, 1),
+            stats("lang/rust", 2, 1),
+            stats("lang/rust/macros", 1, 1),
+            stats("lang/rust/traits", 1, 0),
+            stats("lang-x", 1, 1),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn query_matches_descendant_tags() -> Result<()> {
+        let db = open(":memory:")?;
+        init_schema(&db)?;
+        let rust = text_note("lang/rust/traits", "rust");
+        let rusty = text_note("lang/rusty", "rusty");
+        let lang = text_note("lang", "lang");
+        insert_notes(&db, &[rust.clone(), rusty.clone(), lang.clone()])?;
+
+        let q = query::parse("lang/rust")?;
+        assert_eq!(matching(&db, Some(&q))?, vec![rust.clone()]);
+        let q = query::parse("lang & !lang/rust")?;
+        assert_eq!(matching(&db, Some(&q))?, vec![rusty, lang]);
+        Ok(())
+    }
+
+    // helper functions for tests
+    fn active(db: &Connection) -> Result<Vec<DbNote>> {
+        active_notes(db)?.iter()?.collect()
+    }
+
+    fn matching(db: &Connection, query: Option<&Query>) -> Result<Vec<DbNote>> {
+        matching_notes(db, query)?.iter()?.collect()
+    }
+
     fn text_note(tags: &str, text: &str) -> DbNote {
         DbNote {
             uuid: Uuid::new_v4(),
             ctime: Local::now().with_timezone(&Utc),
             tags: tags.to_string(),
-            data: NoteData::Text(text.to_string())
+            data: NoteData::Text(text.to_string()),
+            parent: None,
         }
     }
 }
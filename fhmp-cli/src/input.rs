@@ -0,0 +1,221 @@
+// Reading notes for `fhmp add` from stdin, files and directories.
+use std::{fmt, fs, io, path::{Path, PathBuf}};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use crate::markdown;
+use crate::note::InputNote;
+use crate::table::{self, Format, TableFormat};
+
+// A YAML document is a note or a sequence of notes. Notes are deserialized
+// right from the document to keep error locations.
+struct Notes(Vec<InputNote>);
+
+struct NotesVisitor;
+
+impl<'de> Visitor<'de> for NotesVisitor {
+    type Value = Notes;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a note or a sequence of notes")
+    }
+
+    // An empty document.
+    fn visit_unit<E: de::Error>(self) -> Result<Notes, E> {
+        Ok(Notes(Vec::new()))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Notes, A::Error> {
+        Vec::deserialize(SeqAccessDeserializer::new(seq)).map(Notes)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Notes, A::Error> {
+        InputNote::deserialize(MapAccessDeserializer::new(map)).map(|n| Notes(vec![n]))
+    }
+}
+
+impl<'de> Deserialize<'de> for Notes {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Notes, D::Error> {
+        d.deserialize_any(NotesVisitor)
+    }
+}
+
+// Reads a stream of YAML documents separated by `---`.
+// Errors of all documents are returned, with their line and column.
+pub fn read_yaml_notes<R: io::Read>(r: R) -> Result<Vec<InputNote>, Vec<String>> {
+    let mut notes = Vec::new();
+    let mut errors = Vec::new();
+    for (i, doc) in serde_yaml::Deserializer::from_reader(r).enumerate() {
+        match Notes::deserialize(doc) {
+            Ok(Notes(n)) => notes.extend(n),
+            Err(e) => {
+                let mut err = e.to_string();
+                // Some errors, e.g. unknown tags, have no location.
+                if !err.contains(" at line ") {
+                    err = format!("{}, in document {}", err, i + 1);
+                }
+                // A syntax error is returned for all following documents.
+                if errors.last().is_some_and(|last| *last == err) {
+                    break;
+                }
+                errors.push(err);
+            }
+        }
+    }
+    if errors.is_empty() { Ok(notes) } else { Err(errors) }
+}
+
+fn read<R: io::Read>(r: R, fmt: &TableFormat) -> Result<Vec<InputNote>, Vec<String>> {
+    match fmt.format {
+        Format::Yaml => read_yaml_notes(r),
+        Format::Markdown => markdown::read_notes(r).map_err(|e| vec![format!("{:#}", e)]),
+        Format::Csv | Format::Tsv =>
+            table::read_notes(r, fmt).map_err(|e| vec![format!("{:#}", e)]),
+    }
+}
+
+pub fn read_stdin(fmt: &TableFormat) -> Result<Vec<InputNote>> {
+    read(io::stdin(), fmt).map_err(|errors| errors_to_anyhow("<stdin>", &errors))
+}
+
+// Reads files and files in directories, recursively. Only files with
+// the extension of the format are read from directories.
+pub fn read_paths(paths: &[String], fmt: &TableFormat) -> Result<Vec<InputNote>> {
+    let mut files = Vec::new();
+    for path in paths {
+        let path = Path::new(path);
+        if path.is_dir() {
+            find_files(path, extensions(fmt.format), &mut files)?;
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+
+    let mut notes = Vec::new();
+    let mut errors = Vec::new();
+    for file in files {
+        let name = file.display().to_string();
+        let res = fs::File::open(&file)
+            .map_err(|e| vec![e.to_string()])
+            .and_then(|f| read(io::BufReader::new(f), fmt));
+        match res {
+            Ok(n) => notes.extend(n),
+            Err(e) => errors.extend(e.iter().map(|e| format!("{}: {}", name, e))),
+        }
+    }
+    if errors.is_empty() {
+        Ok(notes)
+    } else {
+        Err(errors_to_anyhow("", &errors))
+    }
+}
+
+fn extensions(format: Format) -> &'static [&'static str] {
+    match format {
+        Format::Yaml => &["yaml", "yml"],
+        Format::Markdown => &["md"],
+        Format::Csv => &["csv"],
+        Format::Tsv => &["tsv"],
+    }
+}
+
+// Files are sorted to add notes in a predictable order.
+fn find_files(dir: &Path, extensions: &[&str], files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_files(&path, extensions, files)?;
+        } else if path.extension().is_some_and(|ext| extensions.iter().any(|e| ext == *e)) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn errors_to_anyhow(name: &str, errors: &[String]) -> anyhow::Error {
+    let prefix = if name.is_empty() { String::new() } else { format!("{}: ", name) };
+    let err: String = errors.iter()
+        .map(|s| format!("- {}{}\n", prefix, s))
+        .collect();
+    anyhow!(err)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use uuid::Uuid;
+    use crate::note::NoteData;
+
+    fn yaml() -> TableFormat {
+        TableFormat::from_args(&[]).expect("default format").0
+    }
+
+    #[test]
+    fn multiple_documents() {
+        let notes = read_yaml_notes(indoc! {"
+            tags: a
+            data: !text one
+            ---
+            - tags: b
+              data: !text two
+            - tags: c
+              data: !card [q, a]
+            ---
+        "}.as_bytes()).expect("valid notes");
+        assert_eq!(notes.len(), 3);
+        assert_eq!(notes[2].data, NoteData::Card(vec!["q".to_string(), "a".to_string()]));
+    }
+
+    #[test]
+    fn errors_have_locations() {
+        let errors = read_yaml_notes(indoc! {"
+            tags: a
+            data: !txt one
+            ---
+            tags: b
+            data: !text two
+            ---
+            - tags: c
+              date: !text three
+        "}.as_bytes()).err().expect("invalid notes");
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].ends_with("in document 1"), "{}", errors[0]);
+        assert!(errors[1].contains("line 7 column 3"), "{}", errors[1]);
+
+        let errors = read_yaml_notes("tags: a\ndata: [b\n---\ntags: c\n".as_bytes())
+            .err().expect("invalid syntax");
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(errors[errors.len() - 1].contains("line 3 column 1"), "{:?}", errors);
+    }
+
+    #[test]
+    fn read_directories() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("fhmp-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(dir.join("sub"))?;
+        fs::write(dir.join("b.yaml"), "tags: b\ndata: !text b\n")?;
+        fs::write(dir.join("sub/a.yml"), "tags: a\ndata: !text a\n")?;
+        fs::write(dir.join("notes.txt"), "not yaml")?;
+        fs::write(dir.join("c.yaml"), "tags: c\ndata: !card [c]\n---\ntags: c\n")?;
+
+        let res = read_paths(&[dir.join("sub").display().to_string()], &yaml())?;
+        assert_eq!(res.len(), 1);
+
+        let err = read_paths(&[dir.display().to_string()], &yaml())
+            .err().expect("c.yaml has no data").to_string();
+        assert!(err.starts_with(&format!("- {}: ", dir.join("c.yaml").display())), "{}", err);
+        assert_eq!(err.lines().count(), 1);
+
+        fs::remove_file(dir.join("c.yaml"))?;
+        let res = read_paths(&[dir.display().to_string()], &yaml())?;
+        let tags: Vec<_> = res.iter().map(|n| n.tags.as_str()).collect();
+        assert_eq!(tags, ["b", "a"]);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
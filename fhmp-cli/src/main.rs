@@ -4,15 +4,62 @@ use anyhow::Result;
 mod config;
 mod note;
 mod db;
+mod migrations;
+mod scheduler;
+mod policy;
+mod query;
+mod webapp;
+mod anki;
+mod sync;
+mod table;
+mod markdown;
+mod input;
+mod plan;
 mod cmd_new;
 mod cmd_add;
+mod cmd_edit;
 mod cmd_dump;
 mod cmd_review;
+mod cmd_tags;
+mod cmd_db;
+mod cmd_serve;
+mod cmd_sync;
+mod cmd_conflicts;
+mod cmd_import;
+mod cmd_history;
+mod cmd_status;
+mod cmd_search;
+mod cmd_export;
 
 fn help() -> Result<()> {
     println!("Usage:");
-    println!("\tfhmp add − read notes in YAML format from stdin.");
-    println!("\tfhmp review [tags*] − review matching notes from DB.");
+    println!("\tfhmp add [--dry-run] [options] [path...] − read notes in YAML format from files, directories or stdin.");
+    println!("\t\t--dry-run shows what would change without writing to DB, in any position.");
+    println!("\tfhmp edit <uuid-prefix | query> − edit matching notes in $EDITOR.");
+    println!("\tfhmp import webapp <file.json> − import notes and reviews exported from the web app.");
+    println!("\t\tnextReview is used if lastReview is not older than the imported reviews, lastReview itself is not stored.");
+    println!("\tfhmp import anki <deck.apkg> [--reviews] − import basic notes from Anki, with review history.");
+    println!("\tfhmp export anki [--tags <query>] <deck.apkg> − export matching notes as an Anki package.");
+    println!("\tfhmp dump [options] [query] − print notes matching the query in YAML format.");
+    println!("\t\toptions: --format yaml|md|csv|tsv, and for csv and tsv:");
+    println!("\t\t--columns uuid,ctime,tags,front,back,text − rows have no header,");
+    println!("\t\t--quote <char> or --no-quote.");
+    println!("\tfhmp review [query] − review matching notes from DB.");
+    println!("\t\tquery is a boolean expression over tags, e.g. `rust & !async | (math & 5min)`");
+    println!("\tfhmp search [--review] <words>... − find notes containing all the words, `word*` matches prefixes.");
+    println!("\t\t--review starts a review of the found notes, in any position.");
+    println!("\tfhmp history <uuid> − list versions of the note.");
+    println!("\tfhmp diff <uuid> [<hash> <hash>] − compare versions, the current one with its parent by default.");
+    println!("\tfhmp revert <uuid> <hash> − save the data of an older version as the current one.");
+    println!("\tfhmp rm <uuid>... − delete notes, also in databases synced with `fhmp sync`. `fhmp revert` brings them back.");
+    println!("\tfhmp suspend <uuid>... − exclude notes from reviews until `fhmp unsuspend`.");
+    println!("\tfhmp unsuspend <uuid>... − make suspended and buried notes active again.");
+    println!("\tfhmp bury [--until YYYY-MM-DD] <uuid>... − exclude notes from reviews until tomorrow or the date.");
+    println!("\tfhmp tags − print tree of tags with number of notes and due notes.");
+    println!("\tfhmp serve − run sync server for the web app.");
+    println!("\tfhmp sync <path-or-url> − exchange notes and reviews with another database, the URL must be http://.");
+    println!("\tfhmp conflicts − resolve notes edited concurrently on different devices.");
+    println!("\tfhmp db migrate [--dry-run] − upgrade database schema.");
     anyhow::bail!("Invalid arguments.");
 }
 
@@ -27,9 +74,54 @@ fn main() -> Result<()> {
     match args.as_slice() {
         [_, cmd, more_args @ ..] => match &cmd[..] {
             "new" if more_args.is_empty() => cmd_new::exec(),
-            "add" if more_args.is_empty() => cmd_add::exec(),
-            "dump" if more_args.is_empty() => cmd_dump::exec(),
+            "add" => cmd_add::exec(more_args),
+            "edit" => cmd_edit::exec(more_args),
+            "import" => match more_args {
+                [format, file] if format == "webapp" => cmd_import::exec_webapp(file),
+                [format, file] if format == "anki" => cmd_import::exec_anki(file, false),
+                [format, file, flag] if format == "anki" && flag == "--reviews" =>
+                    cmd_import::exec_anki(file, true),
+                _ => help(),
+            },
+            "export" => match more_args {
+                [format, file] if format == "anki" => cmd_export::exec_anki(None, file),
+                [format, flag, query, file] if format == "anki" && flag == "--tags" =>
+                    cmd_export::exec_anki(Some(query), file),
+                _ => help(),
+            },
+            "dump" => cmd_dump::exec(more_args),
             "review" => cmd_review::exec(more_args),
+            "search" => cmd_search::exec(more_args),
+            "history" => match more_args {
+                [uuid] => cmd_history::exec(uuid),
+                _ => help(),
+            },
+            "diff" => match more_args {
+                [uuid] => cmd_history::exec_diff(uuid, None),
+                [uuid, a, b] => cmd_history::exec_diff(uuid, Some((a, b))),
+                _ => help(),
+            },
+            "revert" => match more_args {
+                [uuid, hash] => cmd_history::exec_revert(uuid, hash),
+                _ => help(),
+            },
+            "rm" => cmd_status::exec_rm(more_args),
+            "suspend" => cmd_status::exec_suspend(more_args),
+            "unsuspend" => cmd_status::exec_unsuspend(more_args),
+            "bury" => cmd_status::exec_bury(more_args),
+            "tags" if more_args.is_empty() => cmd_tags::exec(),
+            "serve" if more_args.is_empty() => cmd_serve::exec(),
+            "sync" => match more_args {
+                [remote] => cmd_sync::exec(remote),
+                _ => help(),
+            },
+            "conflicts" if more_args.is_empty() => cmd_conflicts::exec(),
+            "db" => match more_args {
+                [sub] if sub == "migrate" => cmd_db::exec_migrate(false),
+                [sub, flag] if sub == "migrate" && flag == "--dry-run" =>
+                    cmd_db::exec_migrate(true),
+                _ => help(),
+            },
             _     => help(),
         }
         _ => help()
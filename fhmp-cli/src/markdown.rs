@@ -0,0 +1,371 @@
+// Notes in Markdown format, convenient for long answers with code.
+//
+// Each note starts with front matter between `---` lines, followed by its
+// text. Sides of a card are sections under `## Front` and `## Back`
+// headings, there may be several `## Back` sections, or they are separated
+// by `???` lines:
+//
+//     ---
+//     tags: rust, rust/traits
+//     ---
+//     ## Front
+//     What is a blanket implementation?
+//
+//     ## Back
+//     An `impl<T: Trait> Other for T`.
+//
+// These lines are markers everywhere except inside fenced code blocks, a
+// line of text that looks like a marker is escaped with a backslash, e.g.
+// `\---`. Use `***` for horizontal rules. A code block must be closed in
+// the same side, fences of a side with an unclosed block are escaped too.
+// One blank line around a side or text is not a part of it.
+use std::io;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, FixedOffset, Local};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::note::{DbNote, InputNote, NoteData};
+
+const SEPARATOR: &str = "---";
+const SIDE_SEPARATOR: &str = "???";
+const FRONT: &str = "## Front";
+const BACK: &str = "## Back";
+
+#[derive(Serialize, Deserialize)]
+struct FrontMatter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uuid: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ctime: Option<DateTime<FixedOffset>>,
+    #[serde(default)]
+    tags: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<String>,
+}
+
+fn is_marker(line: &str) -> bool {
+    [SEPARATOR, SIDE_SEPARATOR, FRONT, BACK].contains(&line.trim_end())
+}
+
+fn fence_of(line: &str) -> Option<&'static str> {
+    ["```", "~~~"].into_iter()
+        .find(|f| line.trim_start().starts_with(f))
+}
+
+// Marker, an escaped marker or an escaped fence.
+fn needs_escape(line: &str) -> bool {
+    let unescaped = line.trim_start_matches('\\');
+    is_marker(unescaped) || (unescaped.len() < line.len() && fence_of(unescaped).is_some())
+}
+
+// Tracks fenced code blocks, markers inside them are plain text.
+#[derive(Default)]
+struct Fence(Option<&'static str>);
+
+impl Fence {
+    // Returns true if the line is inside of a code block or is its fence.
+    fn update(&mut self, line: &str) -> bool {
+        match (self.0, fence_of(line)) {
+            (None, Some(f)) => self.0 = Some(f),
+            (Some(open), Some(f)) if open == f => self.0 = None,
+            (None, None) => return false,
+            _ => {}
+        }
+        true
+    }
+}
+
+// A line of the note text with escaping removed, or a marker.
+enum Line<'a> {
+    Text(&'a str),
+    Marker(&'a str),
+}
+
+pub fn read_notes<R: io::Read>(mut r: R) -> Result<Vec<InputNote>> {
+    let mut input = String::new();
+    r.read_to_string(&mut input)?;
+
+    let mut lines = input.lines().enumerate().peekable();
+    // Text before the first note is ignored if blank.
+    for (i, line) in lines.by_ref() {
+        if line.trim_end() == SEPARATOR {
+            break;
+        } else if !line.trim().is_empty() {
+            bail!("Line {}: notes must start with front matter after `---`", i + 1);
+        }
+    }
+
+    let mut notes = Vec::new();
+    while lines.peek().is_some() {
+        let start = lines.peek().map_or(0, |(i, _)| i + 1);
+        let mut front_matter = String::new();
+        for (_, line) in lines.by_ref() {
+            if line.trim_end() == SEPARATOR {
+                break;
+            }
+            front_matter.push_str(line);
+            front_matter.push('\n');
+        }
+        let meta: FrontMatter = serde_yaml::from_str(&front_matter)
+            .with_context(|| format!("Line {}: parsing front matter", start))?;
+
+        let mut body = Vec::new();
+        let mut fence = Fence::default();
+        let mut fence_start = 0;
+        for (i, line) in lines.by_ref() {
+            let in_block = fence.0.is_some();
+            if fence.update(line) {
+                if !in_block {
+                    fence_start = i + 1;
+                }
+                body.push(Line::Text(line));
+            } else if line.trim_end() == SEPARATOR {
+                break;
+            } else if is_marker(line) {
+                body.push(Line::Marker(line.trim_end()));
+            } else if needs_escape(line) {
+                body.push(Line::Text(&line[1..]));
+            } else {
+                body.push(Line::Text(line));
+            }
+        }
+        // Otherwise the rest of the file would be the code block.
+        if fence.0.is_some() {
+            bail!("Line {}: code block is not closed", fence_start);
+        }
+
+        notes.push(InputNote {
+            uuid: meta.uuid,
+            ctime: meta.ctime.map(|t| t.with_timezone(&Local)),
+            tags: meta.tags,
+            data: parse_body(&body)
+                .with_context(|| format!("Line {}", start))?,
+            parent: meta.parent,
+        });
+    }
+    Ok(notes)
+}
+
+fn parse_body(body: &[Line]) -> Result<NoteData> {
+    let mut sides = vec![Vec::new()];
+    let mut headings = false;
+    for line in body {
+        match line {
+            Line::Text(text) => sides.last_mut().expect("not empty").push(*text),
+            Line::Marker(m) => {
+                match *m {
+                    FRONT if !headings && sides.len() == 1 => {
+                        if sides[0].iter().any(|l| !l.trim().is_empty()) {
+                            bail!("Text before `{}`", FRONT);
+                        }
+                        sides.clear();
+                        headings = true;
+                    }
+                    BACK if headings => {}
+                    SIDE_SEPARATOR if !headings => {}
+                    _ => bail!("Unexpected `{}`", m),
+                }
+                sides.push(Vec::new());
+            }
+        }
+    }
+
+    let mut sides: Vec<String> = sides.iter().map(|s| join_lines(s)).collect();
+    if sides.len() == 1 {
+        Ok(NoteData::Text(sides.remove(0)))
+    } else {
+        Ok(NoteData::Card(sides))
+    }
+}
+
+// One blank line around the text is not a part of it.
+fn join_lines(lines: &[&str]) -> String {
+    let lines = lines.strip_prefix(&[""]).unwrap_or(lines);
+    let lines = lines.strip_suffix(&[""]).unwrap_or(lines);
+    lines.join("\n")
+}
+
+pub fn write_notes<W: io::Write>(
+    mut w: W,
+    notes: impl Iterator<Item = Result<DbNote>>
+) -> Result<()> {
+    for n in notes {
+        let n = n?;
+        let meta = FrontMatter {
+            uuid: Some(n.uuid),
+            ctime: Some(n.ctime.into()),
+            tags: n.tags.replace('\n', ", "),
+            parent: n.parent.clone(),
+        };
+        write!(w, "{}\n{}{}\n", SEPARATOR, serde_yaml::to_string(&meta)?, SEPARATOR)?;
+        match &n.data {
+            NoteData::Text(text) => write!(w, "\n{}\n\n", escape(text))?,
+            NoteData::Card(sides) => {
+                for (i, side) in sides.iter().enumerate() {
+                    let heading = if i == 0 { FRONT } else { BACK };
+                    write!(w, "{}\n\n{}\n\n", heading, escape(side))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn escape(text: &str) -> String {
+    // Markers after an unclosed fence would be read as text, so there are
+    // no code blocks in such text.
+    let mut fence = Fence::default();
+    for line in text.split('\n') {
+        fence.update(line);
+    }
+    let closed = fence.0.is_none();
+    text.split('\n')
+        .map(|line| {
+            let in_block = closed && fence.update(line);
+            if !in_block && (needs_escape(line) || fence_of(line).is_some()) {
+                format!("\\{}", line)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn read_cards_and_text() -> Result<()> {
+        let notes = read_notes(indoc! {"
+            ---
+            tags: rust, rust/traits
+            ---
+            ## Front
+            What is a blanket implementation?
+
+            ## Back
+            ```rust
+            impl<T: Display> ToString for T {}
+            ---
+            ```
+            ---
+            uuid: 2f1a7b1e-3b5e-4d8a-9c53-8f0d8f4b8f1e
+            tags: math
+            ---
+            2 + 2
+            ???
+            4
+            ---
+            tags: quotes
+            ---
+            \\---
+            Premature optimization.
+        "}.as_bytes())?;
+        assert_eq!(notes.len(), 3);
+        assert_eq!(notes[0].tags, "rust, rust/traits");
+        assert_eq!(notes[0].data, NoteData::Card(vec![
+            "What is a blanket implementation?".to_string(),
+            "```rust\nimpl<T: Display> ToString for T {}\n---\n```".to_string(),
+        ]));
+        assert!(notes[1].uuid.is_some());
+        assert_eq!(notes[1].data, NoteData::Card(vec!["2 + 2".to_string(), "4".to_string()]));
+        assert_eq!(notes[2].data, NoteData::Text("---\nPremature optimization.".to_string()));
+
+        assert!(read_notes("text\n---\n".as_bytes()).is_err());
+        assert!(read_notes("---\n---\n## Back\nno front\n".as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn write_and_read_back() -> Result<()> {
+        let notes = [
+            NoteData::Text("## Front\n\\???\n\n***".to_string()),
+            NoteData::Card(vec![
+                "Q".to_string(),
+                "A\n---".to_string(),
+                "```\n---\n```".to_string(),
+            ]),
+        ];
+        let notes: Vec<DbNote> = notes.into_iter()
+            .map(|data| InputNote {
+                uuid: None,
+                ctime: None,
+                tags: "a,b/c".to_string(),
+                data,
+                parent: None,
+            }.to_db_note())
+            .collect::<Result<_, _>>()?;
+        let notes = [
+            notes[0].clone(),
+            DbNote { parent: Some("abc".to_string()), ..notes[1].clone() },
+        ];
+
+        let mut out = Vec::new();
+        write_notes(&mut out, notes.iter().cloned().map(Ok))?;
+        let read = read_notes(out.as_slice())?;
+        assert_eq!(read.len(), 2);
+        for (r, n) in read.iter().zip(notes.iter()) {
+            let r = r.to_db_note()?;
+            assert_eq!(r.uuid, n.uuid);
+            assert_eq!(r.ctime, n.ctime);
+            assert_eq!(r.tags, n.tags);
+            assert_eq!(r.data, n.data);
+            assert_eq!(r.parent, n.parent);
+        }
+        Ok(())
+    }
+
+    fn round_trip(data: Vec<NoteData>) -> Result<Vec<NoteData>> {
+        let notes: Vec<DbNote> = data.into_iter()
+            .map(|data| InputNote {
+                uuid: None,
+                ctime: None,
+                tags: String::new(),
+                data,
+                parent: None,
+            }.to_db_note())
+            .collect::<Result<_, _>>()?;
+        let mut out = Vec::new();
+        write_notes(&mut out, notes.into_iter().map(Ok))?;
+        read_notes(out.as_slice())?.into_iter()
+            .map(|n| Ok(n.data))
+            .collect()
+    }
+
+    #[test]
+    fn unclosed_code_blocks() -> Result<()> {
+        let data = vec![
+            NoteData::Text("```rust\n---\n\\```".to_string()),
+            NoteData::Card(vec![
+                "~~~\n## Back".to_string(),
+                "```\ncode\n```".to_string(),
+            ]),
+            NoteData::Text("next".to_string()),
+        ];
+        assert_eq!(round_trip(data.clone())?, data);
+
+        let err = read_notes("---\n---\n```\n---\n---\nnext\n".as_bytes())
+            .err().expect("unclosed block").to_string();
+        assert_eq!(err, "Line 3: code block is not closed");
+        Ok(())
+    }
+
+    #[test]
+    fn blank_lines_are_kept() -> Result<()> {
+        let data = vec![
+            NoteData::Text("\n\ntext\n".to_string()),
+            NoteData::Card(vec![
+                "\nQ".to_string(),
+                String::new(),
+                "  indented\n\n".to_string(),
+            ]),
+            NoteData::Text(String::new()),
+        ];
+        assert_eq!(round_trip(data.clone())?, data);
+        Ok(())
+    }
+}
@@ -0,0 +1,640 @@
+// Database schema is versioned with `PRAGMA user_version`.
+// Each migration brings the schema from version N to N+1 where N is the index
+// of the migration in MIGRATIONS. Never edit migrations that were released,
+// add a new one instead.
+use anyhow::{anyhow, Result};
+use rusqlite::Connection;
+
+pub struct Migration {
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "Initial schema",
+        // Databases created before migrations were introduced have
+        // user_version = 0, so this one must be safe to apply to them.
+        sql: "
+        -- This table is a dictionary of note statuses.
+        create table if not exists note_status(
+            id integer primary key,
+            label text
+        );
+
+        insert or ignore into note_status (id, label) values
+            (1, 'active'),  -- is ready for review
+            (2, 'retired'); -- was updated by a newer version or deleted
+
+        -- This table holds our notes with some metadata.
+        -- Notes are read-only. Hash of tags+data is used as a primary key.
+        -- When updating a note, a new version is created and the original one
+        -- is marked as 'retired'.
+        -- UUID is used to link versions together and track history of updates.
+        create table if not exists notes(
+            hash text primary key,
+            uuid text not null,
+            ctime text not null,
+            mtime text,
+            tags text not null,
+            data json not null,
+            status integer not null references note_status(id) default 1
+        );
+        create index if not exists notes_uuid_ix
+            on notes(uuid);
+
+        create trigger if not exists retire_updated_notes
+            before insert on notes
+            begin
+                update notes
+                    set status = 2,
+                        mtime = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+                    where true
+                      and hash <> new.hash
+                      and uuid = new.uuid
+                      and status = 1;
+            end;
+
+        create trigger if not exists add_fresh_notes_to_queue
+            after insert on notes
+            begin
+                insert into queue
+                    (note_id, next_review)
+                    values
+                    (new.uuid, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+                on conflict (note_id) do nothing;
+            end;
+
+        create view if not exists current_notes as
+            select * from notes
+                where status = 1;
+
+        -- This table holds history of reviews.
+        -- Each row references some note and contains a review outcome.
+        create table if not exists review(
+            id integer primary key,
+            note_id text not null references notes(uuid),
+            ctime text not null,
+            result text not null,  -- FIXME: dictionary of possible results?
+            decision json not null -- free form details of the decision
+        );
+
+        -- Queue is used to select notes that are due for review.
+        create table if not exists queue(
+            note_id text not null unique references notes(uuid),
+            next_review text not null
+        );
+        create index if not exists queue_next_review_ix
+            on queue(next_review);
+    "},
+    Migration {
+        description: "Add normalized note_tags table",
+        sql: "
+        -- Tags of each note version, one row per tag.
+        -- This is a normalized copy of `notes.tags` maintained by insert_notes
+        -- to make tag queries use an index.
+        create table if not exists note_tags(
+            note_hash text not null references notes(hash),
+            tag text not null,
+            primary key (tag, note_hash)
+        ) without rowid;
+        create index if not exists note_tags_note_hash_ix
+            on note_tags(note_hash);
+
+        -- Backfill tags of existing notes.
+        insert or ignore into note_tags (note_hash, tag)
+            with recursive split(hash, tag, rest) as (
+                select hash, '', tags || char(10)
+                    from notes
+                union all
+                select
+                    hash,
+                    substr(rest, 1, instr(rest, char(10)) - 1),
+                    substr(rest, instr(rest, char(10)) + 1)
+                from split
+                where rest <> ''
+            )
+            select hash, tag from split where tag <> '';
+    "},
+    Migration {
+        description: "Add review_result dictionary, drop invalid foreign keys",
+        // `notes(uuid)` is not unique and can't be referenced by foreign keys,
+        // so `review` and `queue` are rebuilt without these references.
+        // Trigger that inserts into `queue` must be recreated as it is not
+        // possible to rename a table referenced by a trigger.
+        sql: "
+        -- This table is a dictionary of review results.
+        create table review_result(
+            id integer primary key,
+            label text not null unique
+        );
+
+        insert into review_result (id, label) values
+            (1, 'again'), -- forgot it, show again in a few minutes
+            (2, 'hard'),  -- decrease delay
+            (3, 'good'),  -- increase delay
+            (4, 'easy');  -- increase delay even more
+
+        create table review_new(
+            id integer primary key,
+            note_id text not null,
+            ctime text not null,
+            result integer not null references review_result(id),
+            decision json not null -- free form details of the decision
+        );
+        -- Reviews with other results would be lost, fail instead.
+        create temp trigger check_review_result
+            before insert on review_new
+            when new.result is null
+            begin
+                select raise(abort,
+                    'Unknown review result, expected again, hard, good or easy');
+            end;
+        insert into review_new
+            (id, note_id, ctime, result, decision)
+            select r.id, r.note_id, r.ctime, rr.id, r.decision
+                from review r
+                left join review_result rr on rr.label = r.result;
+        drop trigger check_review_result;
+        drop table review;
+        alter table review_new rename to review;
+        create index review_note_id_ix
+            on review(note_id, ctime);
+        create index review_ctime_ix
+            on review(ctime);
+
+        drop trigger add_fresh_notes_to_queue;
+
+        create table queue_new(
+            note_id text not null unique,
+            next_review text not null
+        );
+        insert into queue_new
+            (note_id, next_review)
+            select note_id, next_review from queue;
+        drop table queue;
+        alter table queue_new rename to queue;
+        create index queue_next_review_ix
+            on queue(next_review);
+
+        create trigger add_fresh_notes_to_queue
+            after insert on notes
+            begin
+                insert into queue
+                    (note_id, next_review)
+                    values
+                    (new.uuid, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+                on conflict (note_id) do nothing;
+            end;
+    "},
+    Migration {
+        description: "Add webapp_notes table",
+        sql: "
+        -- Notes synced with the web app (see webapp.rs).
+        -- The web app has its own note ids and versions.
+        create table webapp_notes(
+            id text primary key,         -- note id in the web app
+            uuid text not null unique,   -- notes(uuid)
+            ver text not null            -- last version received from the app
+        );
+    "},
+    Migration {
+        description: "Add sync_state table, don't retire notes on re-insert",
+        // The trigger used to fire even when the inserted version already
+        // exists, so loading an old version again retired the current one.
+        // Retired versions received by `fhmp sync` must not retire anything
+        // as well.
+        sql: "
+        -- Watermarks of `fhmp sync` for each remote: max rowids of `notes` and
+        -- ids of `review` that were already exchanged.
+        create table sync_state(
+            remote text primary key,
+            pulled_notes integer not null,   -- rowids in the remote DB
+            pulled_reviews integer not null,
+            pushed_notes integer not null,   -- rowids in the local DB
+            pushed_reviews integer not null
+        );
+
+        drop trigger retire_updated_notes;
+
+        create trigger retire_updated_notes
+            before insert on notes
+            when new.status = 1
+             and not exists (select 1 from notes where hash = new.hash)
+            begin
+                update notes
+                    set status = 2,
+                        mtime = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+                    where true
+                      and hash <> new.hash
+                      and uuid = new.uuid
+                      and status = 1;
+            end;
+    "},
+    Migration {
+        description: "Add parent hash of note versions and 'conflict' status",
+        sql: "
+        insert into note_status (id, label) values
+            (3, 'conflict'); -- edited concurrently with the active version
+
+        -- Hash of the version this one is based on.
+        alter table notes add column parent text;
+
+        -- Versions were linearly ordered so far.
+        update notes
+            set parent = (
+                select p.hash from notes p
+                where p.uuid = notes.uuid
+                  and p.rowid < notes.rowid
+                order by p.rowid desc
+                limit 1);
+    "},
+    Migration {
+        description: "Add 'deleted', 'suspended' and 'buried' statuses",
+        // Suspended and buried versions are current but are not reviewed,
+        // a new version replaces them like an active one.
+        sql: "
+        insert into note_status (id, label) values
+            (4, 'deleted'),   -- removed with `fhmp rm`
+            (5, 'suspended'), -- not reviewed until unsuspended
+            (6, 'buried');    -- not reviewed until `buried_until`
+
+        alter table notes add column buried_until text;
+
+        drop trigger retire_updated_notes;
+
+        create trigger retire_updated_notes
+            before insert on notes
+            when new.status in (1, 5, 6)
+             and not exists (select 1 from notes where hash = new.hash)
+            begin
+                update notes
+                    set status = 2,
+                        mtime = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+                    where true
+                      and hash <> new.hash
+                      and uuid = new.uuid
+                      and status in (1, 5, 6);
+            end;
+    "},
+    Migration {
+        description: "Add notes_fts full-text index",
+        // Versions are referenced by hash, as rowids of `notes` may change
+        // on vacuum. Versions are read-only, so updates need no trigger.
+        sql: "
+        -- Text of all sides of a note and its tags, for `fhmp search`.
+        create virtual table notes_fts using fts5(
+            hash unindexed,
+            text,
+            tags,
+            tokenize = 'porter unicode61 remove_diacritics 2'
+        );
+
+        create trigger index_inserted_notes
+            after insert on notes
+            begin
+                insert into notes_fts (hash, text, tags)
+                    values (
+                        new.hash,
+                        coalesce(
+                            json_extract(new.data, '$.text'),
+                            (select group_concat(value, char(10))
+                                from json_each(new.data, '$.card'))),
+                        replace(new.tags, char(10), ' '));
+            end;
+
+        create trigger unindex_deleted_notes
+            after delete on notes
+            begin
+                delete from notes_fts where hash = old.hash;
+            end;
+
+        insert into notes_fts (hash, text, tags)
+            select
+                hash,
+                coalesce(
+                    json_extract(data, '$.text'),
+                    (select group_concat(value, char(10))
+                        from json_each(data, '$.card'))),
+                replace(tags, char(10), ' ')
+            from notes;
+    "},
+    Migration {
+        description: "Add change sequence and status time of note versions",
+        // `fhmp sync` sends versions changed since the last sync, including
+        // status changes. Sequence numbers start from rowids, so watermarks
+        // in `sync_state` stay valid.
+        sql: "
+        alter table notes add column seq integer;
+        -- Time of the last status change, null for new versions.
+        alter table notes add column stime text;
+
+        update notes set seq = rowid;
+        create index notes_seq_ix
+            on notes(seq);
+
+        create trigger number_inserted_notes
+            after insert on notes
+            begin
+                update notes
+                    set seq = (select coalesce(max(seq), 0) + 1 from notes)
+                    where hash = new.hash;
+            end;
+
+        -- Status changes received by `fhmp sync` keep their time.
+        create trigger number_updated_notes
+            after update of status, buried_until on notes
+            begin
+                update notes
+                    set seq = (select coalesce(max(seq), 0) + 1 from notes),
+                        stime = case when new.stime is old.stime
+                            then strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                            else new.stime end
+                    where hash = new.hash;
+            end;
+    "},
+    Migration {
+        description: "Replace deleted versions with new ones",
+        // A deleted version stays current, so a new version of a deleted
+        // note, e.g. received by `fhmp sync`, replaces it and is deleted too.
+        sql: "
+        drop trigger retire_updated_notes;
+
+        create trigger retire_updated_notes
+            before insert on notes
+            when new.status in (1, 4, 5, 6)
+             and not exists (select 1 from notes where hash = new.hash)
+            begin
+                update notes
+                    set status = 2,
+                        mtime = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+                    where true
+                      and hash <> new.hash
+                      and uuid = new.uuid
+                      and status in (1, 4, 5, 6);
+            end;
+    "},
+];
+
+pub fn latest_version() -> usize {
+    MIGRATIONS.len()
+}
+
+fn current_version(db: &Connection) -> Result<usize> {
+    let v: usize = db.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    Ok(v)
+}
+
+// Databases created by a newer version of fhmp may have incompatible schema.
+pub fn check_version(db: &Connection) -> Result<()> {
+    let v = current_version(db)?;
+    if v > latest_version() {
+        Err(anyhow!(
+            "Database schema version {} is newer than supported version {}. \
+            Please upgrade fhmp.", v, latest_version()))
+    } else {
+        Ok(())
+    }
+}
+
+// Migrations that are not yet applied, with their target versions.
+pub fn pending(db: &Connection) -> Result<Vec<(usize, &'static Migration)>> {
+    check_version(db)?;
+    let v = current_version(db)?;
+    Ok(MIGRATIONS.iter().enumerate()
+        .skip(v)
+        .map(|(i, m)| (i + 1, m))
+        .collect())
+}
+
+// Applies all pending migrations in a single transaction.
+// Foreign keys are enforced only when the schema is up to date, as
+// old versions of the schema have invalid references.
+pub fn migrate(db: &Connection) -> Result<()> {
+    migrate_to(db, latest_version())?;
+    db.pragma_update(None, "foreign_keys", true)?;
+    Ok(())
+}
+
+fn migrate_to(db: &Connection, version: usize) -> Result<()> {
+    let pending = pending(db)?;
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let tx = db.unchecked_transaction()?;
+    for (v, m) in pending.into_iter().take_while(|(v, _)| *v <= version) {
+        tx.execute_batch(m.sql)
+            .map_err(|e| anyhow!("Migration to version {} failed: {}", v, e))?;
+        tx.pragma_update(None, "user_version", v)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::open;
+
+    #[test]
+    fn migrate_fresh_database() -> Result<()> {
+        let db = open(":memory:")?;
+        assert_eq!(pending(&db)?.len(), latest_version());
+        migrate(&db)?;
+        assert_eq!(current_version(&db)?, latest_version());
+        assert!(pending(&db)?.is_empty());
+        // nothing to do on the second run
+        migrate(&db)
+    }
+
+    #[test]
+    fn migrate_database_without_version() -> Result<()> {
+        // This is what init_schema did before migrations were introduced.
+        let db = open(":memory:")?;
+        db.execute_batch(MIGRATIONS[0].sql)?;
+        db.execute_batch(MIGRATIONS[1].sql)?;
+        assert_eq!(current_version(&db)?, 0);
+        migrate(&db)?;
+        assert_eq!(current_version(&db)?, latest_version());
+        Ok(())
+    }
+
+    #[test]
+    fn refuse_newer_database() -> Result<()> {
+        let db = open(":memory:")?;
+        db.pragma_update(None, "user_version", latest_version() + 1)?;
+        assert!(check_version(&db).is_err());
+        assert!(migrate(&db).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn failed_migration_is_rolled_back() -> Result<()> {
+        let db = open(":memory:")?;
+        migrate_to(&db, 1)?;
+        db.execute("drop table notes", [])?;
+        assert!(migrate(&db).is_err());
+        assert_eq!(current_version(&db)?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn review_results_are_converted() -> Result<()> {
+        let db = open(":memory:")?;
+        migrate_to(&db, 2)?;
+        db.execute_batch("
+            insert into notes (hash, uuid, ctime, tags, data) values
+                ('h1', 'u1', '', '', '{}');
+            insert into review (note_id, ctime, result, decision) values
+                ('u1', '2024-01-01T00:00:00Z', 'easy', '{}'),
+                ('u1', '2024-01-02T00:00:00Z', 'again', '{}');
+        ")?;
+        migrate(&db)?;
+
+        let mut q = db.prepare("select result from review order by ctime")?;
+        let results = q.query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<i64>, _>>()?;
+        assert_eq!(results, vec![4, 1]);
+
+        // trigger still works after `queue` is rebuilt
+        db.execute("
+            insert into notes (hash, uuid, ctime, tags, data) values
+                ('h2', 'u2', '', '', '{}')", [])?;
+        let n: i64 = db.query_row("select count(*) from queue", [], |row| row.get(0))?;
+        assert_eq!(n, 2);
+
+        let res = db.execute("
+            insert into review (note_id, ctime, result, decision) values
+                ('u1', '2024-01-03T00:00:00Z', 5, '{}')", []);
+        assert!(res.is_err(), "foreign key is enforced");
+        Ok(())
+    }
+
+    #[test]
+    fn note_tags_are_backfilled() -> Result<()> {
+        let db = open(":memory:")?;
+        migrate_to(&db, 1)?;
+        db.execute_batch("
+            insert into notes (hash, uuid, ctime, tags, data) values
+                ('h1', 'u1', '', 'go' || char(10) || 'lang/rust', '{}'),
+                ('h2', 'u2', '', '', '{}');
+        ")?;
+        migrate(&db)?;
+
+        let mut q = db.prepare("select note_hash, tag from note_tags order by tag")?;
+        let tags = q.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, String)>, _>>()?;
+        assert_eq!(tags, vec![
+            ("h1".to_string(), "go".to_string()),
+            ("h1".to_string(), "lang/rust".to_string()),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn note_parents_are_backfilled() -> Result<()> {
+        let db = open(":memory:")?;
+        migrate_to(&db, 5)?;
+        db.execute_batch("
+            insert into notes (hash, uuid, ctime, tags, data) values
+                ('h1', 'u1', '', '', '{}'),
+                ('h2', 'u2', '', '', '{}'),
+                ('h3', 'u1', '', '', '{}');
+        ")?;
+        migrate(&db)?;
+
+        let mut q = db.prepare("select hash, parent from notes order by hash")?;
+        let parents = q.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, Option<String>)>, _>>()?;
+        assert_eq!(parents, vec![
+            ("h1".to_string(), None),
+            ("h2".to_string(), None),
+            ("h3".to_string(), Some("h1".to_string())),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_review_results_fail_migration() -> Result<()> {
+        let db = open(":memory:")?;
+        migrate_to(&db, 2)?;
+        db.execute_batch("
+            insert into review (note_id, ctime, result, decision) values
+                ('u1', '2024-01-01T00:00:00Z', 'easy', '{}'),
+                ('u1', '2024-01-02T00:00:00Z', 'meh', '{}');
+        ")?;
+        let err = migrate(&db).err().map(|e| e.to_string()).unwrap_or_default();
+        assert!(err.contains("Unknown review result"), "{}", err);
+        assert_eq!(current_version(&db)?, 2);
+        let n: i64 = db.query_row("select count(*) from review", [], |row| row.get(0))?;
+        assert_eq!(n, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn existing_notes_are_indexed() -> Result<()> {
+        let db = open(":memory:")?;
+        migrate_to(&db, 7)?;
+        db.execute_batch(r#"
+            insert into notes (hash, uuid, ctime, tags, data) values
+                ('h1', 'u1', '', 'rust', '{"card":["lifetimes","annotations"]}'),
+                ('h2', 'u2', '', 'math', '{"text":"primes"}');
+        "#)?;
+        migrate(&db)?;
+
+        let find = |q: &str| -> Result<Vec<String>> {
+            let mut s = db.prepare("select hash from notes_fts where notes_fts match ?")?;
+            let res = s.query_map([q], |row| row.get(0))?.collect::<Result<_, _>>()?;
+            Ok(res)
+        };
+        assert_eq!(find("annotations")?, vec!["h1"]);
+        assert_eq!(find("tags:math")?, vec!["h2"]);
+
+        // new versions are indexed by the trigger
+        db.execute("
+            insert into notes (hash, uuid, ctime, tags, data) values
+                ('h3', 'u3', '', 'math', '{\"text\":\"more primes\"}')", [])?;
+        assert_eq!(find("primes")?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn changes_are_numbered() -> Result<()> {
+        let db = open(":memory:")?;
+        migrate_to(&db, 8)?;
+        db.execute_batch("
+            insert into notes (hash, uuid, ctime, tags, data) values
+                ('h1', 'u1', '', '', '{}'),
+                ('h2', 'u2', '', '', '{}');
+            delete from notes where hash = 'h1';
+        ")?;
+        migrate(&db)?;
+
+        let seq = |hash: &str| -> Result<(i64, Option<String>)> {
+            let res = db.query_row(
+                "select seq, stime from notes where hash = ?",
+                [hash],
+                |row| Ok((row.get(0)?, row.get(1)?)))?;
+            Ok(res)
+        };
+        // existing versions keep their rowids
+        assert_eq!(seq("h2")?, (2, None));
+
+        db.execute("
+            insert into notes (hash, uuid, ctime, tags, data) values
+                ('h3', 'u3', '', '', '{}')", [])?;
+        assert_eq!(seq("h3")?, (3, None));
+        db.execute("update notes set status = 5 where hash = 'h2'", [])?;
+        let (n, stime) = seq("h2")?;
+        assert_eq!(n, 4);
+        assert!(stime.is_some());
+        // a received status change keeps its time
+        db.execute("
+            update notes set status = 1, stime = '2020-01-01T00:00:00.000Z'
+            where hash = 'h2'", [])?;
+        assert_eq!(seq("h2")?, (5, Some("2020-01-01T00:00:00.000Z".to_string())));
+        Ok(())
+    }
+}
@@ -2,6 +2,7 @@
 // We have different representations for notes in DB and notes not yet in DB.
 use chrono::{DateTime, Utc, Local};
 use serde::{Serialize, Deserialize};
+use similar::TextDiff;
 use sha3::{Shake128, digest::{Update, ExtendableOutput, XofReader}};
 use thiserror::Error;
 use uuid::Uuid;
@@ -27,6 +28,11 @@ pub struct DbNote {
     pub ctime: DateTime<Utc>,
     pub tags: String,
     pub data: NoteData,
+    // Hash of the version this one is based on. It is used to detect
+    // concurrent edits when inserting the note, None means the current
+    // version. It is not loaded from the DB.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
 }
 
 impl DbNote {
@@ -58,7 +64,9 @@ pub struct InputNote {
     pub uuid: Option<Uuid>,
     pub ctime: Option<DateTime<Local>>,
     pub tags: String,
-    pub data: NoteData
+    pub data: NoteData,
+    // `fhmp dump` sets it to the hash of the dumped version.
+    pub parent: Option<String>,
 }
 
 #[derive(Error, PartialEq, Debug)]
@@ -76,20 +84,50 @@ impl InputNote {
         let ctime = self.ctime.unwrap_or_else(Local::now).with_timezone(&Utc);
         let mut tags = self.tags
             .split(',')
-            .map(|s| s.trim().to_lowercase())
+            .map(normalize_tag)
             .collect::<Vec<_>>();
         tags.sort();
+        tags.dedup();
         let tags = tags.join("\n"); // list of tags is \n delimited
 
         match &self.data {
             NoteData::Card(items) if items.len() < 2 =>
                 Err(NoteParseError::InvalidCard),
             _ =>
-                Ok(DbNote { uuid, ctime, tags, data: self.data.clone() })
+                Ok(DbNote {
+                    uuid,
+                    ctime,
+                    tags,
+                    data: self.data.clone(),
+                    parent: self.parent.clone(),
+                })
         }
     }
 }
 
+// Unified diff of tags and data of two versions of a note.
+pub fn diff(old: &DbNote, new: &DbNote, old_name: &str, new_name: &str) -> String {
+    let render = |n: &DbNote| {
+        let data = serde_yaml::to_string(&n.data)
+            .expect("Serializing struct to YAML should always succeed.");
+        format!("tags: {}\n{}", n.tags.replace('\n', ", "), data)
+    };
+    let (old, new) = (render(old), render(new));
+    TextDiff::from_lines(&old, &new)
+        .unified_diff()
+        .header(old_name, new_name)
+        .to_string()
+}
+
+// Tags are hierarchical with `/` as a separator, e.g. `lang/rust/traits`.
+fn normalize_tag(tag: &str) -> String {
+    tag.split('/')
+        .map(|s| s.trim().to_lowercase())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -104,7 +142,8 @@ mod tests {
             uuid: Uuid::new_v4(),
             ctime: Local::now().with_timezone(&Utc),
             tags: "hello\nworld".to_string(),
-            data: NoteData::Text("first note".to_string())
+            data: NoteData::Text("first note".to_string()),
+            parent: None,
         };
         let (h0, _) = note0.hash_and_json();
 
@@ -153,6 +192,17 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn input_note_hierarchical_tags() -> Result<()> {
+        let input_note: InputNote = serde_yaml::from_str(indoc!("
+            tags: Lang / Rust/traits/, lang/rust//traits, /math
+            data: !text hello
+        "))?;
+        let db_note = input_note.to_db_note()?;
+        assert_eq!(db_note.tags, "lang/rust/traits\nmath");
+        Ok(())
+    }
+
     #[test]
     fn input_note_to_db_note_errs() -> Result<()> {
         let input_note: InputNote = serde_yaml::from_str(indoc!("
@@ -0,0 +1,133 @@
+// Change plan of `fhmp add --dry-run`.
+use anyhow::Result;
+use rusqlite::Connection;
+use crate::db::{self, current_note, note_exists, Inserted};
+use crate::note::DbNote;
+
+pub enum Change {
+    New,
+    // The version is already in the DB, it may be an old one.
+    Unchanged,
+    // A new version of the current one.
+    Updated(DbNote),
+    // The note is in the DB but has no current version.
+    Resurrected,
+    // A new version of a deleted note, it is deleted as well.
+    Deleted,
+    // Based on an older version than the current one, see `fhmp conflicts`.
+    Conflict(DbNote),
+}
+
+impl Change {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Change::New => "new",
+            Change::Unchanged => "unchanged",
+            Change::Updated(_) => "updated",
+            Change::Resurrected => "resurrected",
+            Change::Deleted => "deleted",
+            Change::Conflict(_) => "conflict",
+        }
+    }
+}
+
+// Notes are inserted in a transaction that is rolled back, so the plan
+// follows the rules of `insert_notes`, including notes repeated in the input.
+pub fn plan(db: &Connection, notes: &[DbNote]) -> Result<Vec<Change>> {
+    let tx = db.unchecked_transaction()?;
+    let mut res = Vec::new();
+    for n in notes {
+        let current = current_note(&tx, &n.uuid)?;
+        let known = note_exists(&tx, &n.uuid)?;
+        let change = match (db::insert_note(&tx, n)?, current) {
+            (Inserted::Exists, _) => Change::Unchanged,
+            (_, None) if db::note_status(&tx, &n.hash_and_json().0)? == Some(4) =>
+                Change::Deleted,
+            (_, None) if known => Change::Resurrected,
+            (_, None) => Change::New,
+            (Inserted::New, Some(c)) => Change::Updated(c),
+            (Inserted::Conflict, Some(c)) => Change::Conflict(c),
+        };
+        res.push(change);
+    }
+    tx.rollback()?;
+    Ok(res)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{init_schema, insert_notes, insert_note_row, NoteRow};
+    use crate::note::{diff, InputNote, NoteData};
+
+    fn note(tags: &str, text: &str) -> DbNote {
+        InputNote {
+            uuid: None,
+            ctime: None,
+            tags: tags.to_string(),
+            data: NoteData::Text(text.to_string()),
+            parent: None,
+        }.to_db_note().expect("valid note")
+    }
+
+    fn count_versions(db: &Connection) -> Result<i64> {
+        Ok(db.query_row("select count(*) from notes", [], |r| r.get(0))?)
+    }
+
+    #[test]
+    fn classify_notes() -> Result<()> {
+        let db = db::open(":memory:")?;
+        init_schema(&db)?;
+        let kept = note("a", "kept");
+        let edited = note("a", "old\ntext");
+        let stale = note("a", "stale");
+        insert_notes(&db, &[kept.clone(), edited.clone(), stale.clone()])?;
+        let (stale_hash, _) = stale.hash_and_json();
+        insert_notes(&db, &[DbNote { uuid: stale.uuid, ..note("a", "stale 2") }])?;
+
+        let retired = note("a", "retired");
+        let (hash, data) = retired.hash_and_json();
+        insert_note_row(&db, &NoteRow {
+            hash,
+            uuid: retired.uuid.to_string(),
+            ctime: retired.ctime.to_rfc3339(),
+            mtime: None,
+            tags: retired.tags.clone(),
+            data,
+            parent: None,
+            status: 2,
+            buried_until: None,
+            stime: None,
+        })?;
+        let deleted = note("a", "deleted");
+        insert_notes(&db, std::slice::from_ref(&deleted))?;
+        db::set_note_status(&db, &deleted.uuid, &db::NoteStatus::Deleted)?;
+        let versions = count_versions(&db)?;
+
+        let notes = vec![
+            note("b", "new"),
+            kept.clone(),
+            DbNote { uuid: edited.uuid, ..note("a", "new\ntext") },
+            DbNote { uuid: retired.uuid, ..note("a", "back") },
+            DbNote { uuid: stale.uuid, parent: Some(stale_hash), ..note("a", "stale 3") },
+            DbNote { uuid: deleted.uuid, ..note("a", "edited") },
+        ];
+        let changes = plan(&db, &notes)?;
+        let labels: Vec<_> = changes.iter().map(Change::label).collect();
+        assert_eq!(labels, ["new", "unchanged", "updated", "resurrected", "conflict", "deleted"]);
+        assert_eq!(count_versions(&db)?, versions);
+
+        let Change::Updated(current) = &changes[2] else { panic!("updated expected") };
+        assert_eq!(diff(current, &notes[2], "current", "new"), "\
+            --- current\n\
+            +++ new\n\
+            @@ -1,4 +1,4 @@\n \
+            tags: a\n \
+            !text |-\n\
+            -  old\n\
+            +  new\n   \
+            text\n");
+        Ok(())
+    }
+}
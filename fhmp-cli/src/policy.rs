@@ -0,0 +1,147 @@
+// Scheduling policies allow to use different algorithms for different kinds of
+// notes. E.g. cards that can be answered in 5 seconds and exercises that
+// require 20 minutes with pencil and paper.
+//
+// Policies are listed in the config file and the first one matching any of
+// the note's tags is used:
+//
+//     [[policy]]
+//     tag = "20min"
+//     algorithm = "fibonacci"
+//     intervals = [3, 7, 14, 30, 60]
+//     max_interval = 60
+//     daily_cap = 2
+//
+// Notes that don't match any policy are scheduled by the `[scheduler]`.
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+
+use crate::scheduler::{Review, Scheduler, SchedulerConfig};
+
+#[derive(Deserialize)]
+pub struct Policy {
+    // Tag pattern, `*` matches any sequence of characters.
+    pub tag: String,
+    // Name to refer to the policy in `review.decision`, defaults to `tag`.
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub scheduler: SchedulerConfig,
+    // Upper limit for the interval between reviews, in days.
+    pub max_interval: Option<f64>,
+    // Max number of reviews per day for notes matching this policy.
+    pub daily_cap: Option<usize>,
+}
+
+impl Policy {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.tag)
+    }
+
+    // `tags` are \n delimited as in `DbNote::tags`.
+    pub fn matches(&self, tags: &str) -> bool {
+        tags.split('\n').any(|t| glob_match(&self.tag, t))
+    }
+
+    pub fn next_review(
+        &self,
+        ctime: DateTime<Utc>,
+        history: &[Review]
+    ) -> DateTime<Utc> {
+        let next_review = self.scheduler().next_review(ctime, history);
+        match (self.max_interval, history.last()) {
+            (Some(days), Some(last)) => {
+                let max = Duration::seconds((days * 86400.0) as i64);
+                next_review.min(last.ctime + max)
+            },
+            _ => next_review,
+        }
+    }
+
+    pub fn scheduler(&self) -> &dyn Scheduler {
+        self.scheduler.scheduler()
+    }
+}
+
+pub fn resolve_policy<'a>(policies: &'a [Policy], tags: &str) -> Option<&'a Policy> {
+    policies.iter().find(|p| p.matches(tags))
+}
+
+fn glob_match(pattern: &str, s: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == s,
+        Some((prefix, rest)) => {
+            let Some(s) = s.strip_prefix(prefix) else {
+                return false;
+            };
+            // Try to match the rest of the pattern at every position.
+            s.char_indices()
+                .map(|(i, _)| i)
+                .chain([s.len()])
+                .any(|i| glob_match(rest, &s[i..]))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::ReviewResult;
+
+    fn policy(toml: &str) -> Policy {
+        config::Config::builder()
+            .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+            .build()
+            .and_then(|c| c.try_deserialize())
+            .expect("valid policy")
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("5min", "5min"));
+        assert!(!glob_match("5min", "15min"));
+        assert!(glob_match("*min", "15min"));
+        assert!(glob_match("lang*", "lang"));
+        assert!(glob_match("l*g*t", "lang/rust"));
+        assert!(!glob_match("l*g*s", "lang/rust"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn resolve_first_matching_policy() {
+        let policies = vec![
+            policy("tag = \"5sec\"\nalgorithm = \"sm2\""),
+            policy("tag = \"*min\"\nname = \"slow\"\nalgorithm = \"fsrs\""),
+        ];
+        let p = resolve_policy(&policies, "5sec\nmath");
+        assert_eq!(p.map(Policy::name), Some("5sec"));
+        let p = resolve_policy(&policies, "20min\nmath");
+        assert_eq!(p.map(Policy::name), Some("slow"));
+        assert_eq!(p.map(|p| p.scheduler().name()), Some("fsrs"));
+        assert!(resolve_policy(&policies, "math").is_none());
+    }
+
+    #[test]
+    fn policy_params() {
+        let p = policy("
+            tag = \"20min\"
+            algorithm = \"fibonacci\"
+            intervals = [3, 7, 14, 30, 60]
+            max_interval = 10
+            daily_cap = 2
+        ");
+        assert_eq!(p.daily_cap, Some(2));
+        assert_eq!(p.scheduler().params()["intervals"][1], 7);
+
+        let ctime = Utc::now();
+        let history: Vec<_> = [0, 3, 10, 24]
+            .iter()
+            .map(|d| Review {
+                ctime: ctime + Duration::days(*d),
+                result: ReviewResult::Easy,
+            })
+            .collect();
+        let next = p.next_review(ctime, &history);
+        assert_eq!(next - history[3].ctime, Duration::days(10));
+    }
+}
@@ -0,0 +1,275 @@
+// Boolean queries over note tags, e.g. `rust & !async | (math & 5min)`.
+//
+// `!` has the highest precedence, then `&` and then `|`.
+// Tags separated by whitespace only are joined with `&`, so `rust math` is
+// the same as `rust & math`.
+// Tags are hierarchical: `lang/rust` matches `lang/rust/traits` as well.
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Clone)]
+#[cfg_attr(test, derive(PartialEq, Debug))]
+pub enum Query {
+    Tag(String),
+    Not(Box<Query>),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+}
+
+#[derive(Error, PartialEq, Debug)]
+pub struct QueryError {
+    pub query: String,
+    // Position of the offending token (in chars).
+    pub pos: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.msg)?;
+        writeln!(f, "  {}", self.query)?;
+        write!(f, "  {}^", " ".repeat(self.pos))
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Tag(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Tag(t) => write!(f, "tag `{}`", t),
+            Token::And => write!(f, "`&`"),
+            Token::Or => write!(f, "`|`"),
+            Token::Not => write!(f, "`!`"),
+            Token::Open => write!(f, "`(`"),
+            Token::Close => write!(f, "`)`"),
+            Token::End => write!(f, "end of query"),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Vec<(usize, Token)> {
+    let mut res = Vec::new();
+    let mut chars = s.chars().enumerate().peekable();
+    while let Some((pos, c)) = chars.next() {
+        let tok = match c {
+            '&' => Token::And,
+            '|' => Token::Or,
+            '!' => Token::Not,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            c if c.is_whitespace() => continue,
+            c => {
+                let mut tag = c.to_lowercase().to_string();
+                while let Some((_, c)) = chars.peek() {
+                    if c.is_whitespace() || "&|!()".contains(*c) {
+                        break;
+                    }
+                    tag.extend(c.to_lowercase());
+                    chars.next();
+                }
+                Token::Tag(tag)
+            }
+        };
+        res.push((pos, tok));
+    }
+    res.push((s.chars().count(), Token::End));
+    res
+}
+
+struct Parser<'a> {
+    query: &'a str,
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.next].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let tok = self.tokens[self.next].1.clone();
+        if tok != Token::End {
+            self.next += 1;
+        }
+        tok
+    }
+
+    fn error(&self, msg: String) -> QueryError {
+        QueryError {
+            query: self.query.to_string(),
+            pos: self.tokens[self.next].0,
+            msg,
+        }
+    }
+
+    fn or(&mut self) -> Result<Query, QueryError> {
+        let mut q = self.and()?;
+        while *self.peek() == Token::Or {
+            self.advance();
+            q = Query::Or(Box::new(q), Box::new(self.and()?));
+        }
+        Ok(q)
+    }
+
+    fn and(&mut self) -> Result<Query, QueryError> {
+        let mut q = self.unary()?;
+        loop {
+            match self.peek() {
+                Token::And => { self.advance(); },
+                Token::Tag(_) | Token::Not | Token::Open => {},
+                _ => return Ok(q),
+            }
+            q = Query::And(Box::new(q), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Query, QueryError> {
+        match self.peek().clone() {
+            Token::Not => {
+                self.advance();
+                Ok(Query::Not(Box::new(self.unary()?)))
+            },
+            Token::Tag(t) => {
+                self.advance();
+                Ok(Query::Tag(t))
+            },
+            Token::Open => {
+                self.advance();
+                let q = self.or()?;
+                match self.peek() {
+                    Token::Close => {
+                        self.advance();
+                        Ok(q)
+                    },
+                    tok => Err(self.error(
+                        format!("Expected `)`, found {}", tok))),
+                }
+            },
+            tok => Err(self.error(
+                format!("Expected a tag, `!` or `(`, found {}", tok))),
+        }
+    }
+}
+
+pub fn parse(s: &str) -> Result<Query, QueryError> {
+    let mut p = Parser { query: s, tokens: tokenize(s), next: 0 };
+    let q = p.or()?;
+    match p.peek() {
+        Token::End => Ok(q),
+        tok => Err(p.error(format!("Unexpected {}", tok))),
+    }
+}
+
+// Command line arguments are joined into a single query.
+// No arguments means "all notes".
+pub fn from_args(args: &[String]) -> Result<Option<Query>, QueryError> {
+    if args.is_empty() {
+        Ok(None)
+    } else {
+        parse(&args.join(" ")).map(Some)
+    }
+}
+
+impl Query {
+    // SQL condition over the `notes n` table.
+    // Values of the query parameters are pushed into `params`.
+    pub fn to_sql(&self, params: &mut Vec<String>) -> String {
+        match self {
+            Query::Tag(t) => {
+                // Descendants of `a/b` are in the range ('a/b/', 'a/b0')
+                // as '0' is the next char after '/'.
+                // This allows to use the index on `note_tags(tag)`.
+                params.push(t.clone());
+                params.push(format!("{}/", t));
+                params.push(format!("{}0", t));
+                "exists (
+                    select 1 from note_tags t
+                    where t.note_hash = n.hash
+                      and (t.tag = ? or (t.tag > ? and t.tag < ?)))".to_string()
+            },
+            Query::Not(q) =>
+                format!("not {}", q.to_sql(params)),
+            Query::And(a, b) =>
+                format!("({} and {})", a.to_sql(params), b.to_sql(params)),
+            Query::Or(a, b) =>
+                format!("({} or {})", a.to_sql(params), b.to_sql(params)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(t: &str) -> Box<Query> { Box::new(Query::Tag(t.to_string())) }
+    fn not(q: Box<Query>) -> Box<Query> { Box::new(Query::Not(q)) }
+    fn and(a: Box<Query>, b: Box<Query>) -> Box<Query> { Box::new(Query::And(a, b)) }
+    fn or(a: Box<Query>, b: Box<Query>) -> Box<Query> { Box::new(Query::Or(a, b)) }
+
+    #[test]
+    fn parse_with_precedence() {
+        assert_eq!(
+            parse("rust & !async | (math & 5min)"),
+            Ok(*or(
+                and(tag("rust"), not(tag("async"))),
+                and(tag("math"), tag("5min")))));
+        assert_eq!(
+            parse("a | b & c"),
+            Ok(*or(tag("a"), and(tag("b"), tag("c")))));
+        assert_eq!(
+            parse("!!(A|b)"),
+            Ok(*not(not(or(tag("a"), tag("b"))))));
+    }
+
+    #[test]
+    fn whitespace_means_and() {
+        assert_eq!(
+            parse("rust  math|go"),
+            Ok(*or(and(tag("rust"), tag("math")), tag("go"))));
+        assert_eq!(
+            from_args(&["rust".to_string(), "!async".to_string()]),
+            Ok(Some(*and(tag("rust"), not(tag("async"))))));
+        assert_eq!(from_args(&[]), Ok(None));
+    }
+
+    #[test]
+    fn errors_point_at_token() {
+        let err = parse("rust & | math").unwrap_err();
+        assert_eq!(err.pos, 7);
+        assert_eq!(err.to_string(), [
+            "Expected a tag, `!` or `(`, found `|`",
+            "  rust & | math",
+            "         ^",
+        ].join("\n"));
+
+        let err = parse("(rust | math").unwrap_err();
+        assert_eq!((err.pos, err.msg.as_str()), (12, "Expected `)`, found end of query"));
+
+        let err = parse("rust)").unwrap_err();
+        assert_eq!((err.pos, err.msg.as_str()), (4, "Unexpected `)`"));
+
+        let err = parse("").unwrap_err();
+        assert_eq!(err.pos, 0);
+    }
+
+    #[test]
+    fn compile_to_sql() {
+        let mut params = Vec::new();
+        let sql = parse("a & !b").unwrap().to_sql(&mut params);
+        assert_eq!(params, vec!["a", "a/", "a0", "b", "b/", "b0"]);
+        assert!(sql.starts_with("(exists"));
+        assert!(sql.contains("and not exists"));
+    }
+}
@@ -0,0 +1,523 @@
+// Spaced repetition algorithms that decide when a note should be reviewed next.
+//
+// Schedulers are stateless: the next review is computed by replaying the whole
+// review history of a note. This way switching to another algorithm (or
+// tuning its parameters) is applied to all notes on their next review.
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Deserialize, Deserializer};
+
+#[derive(Clone, Copy)]
+#[cfg_attr(test, derive(PartialEq, Debug))]
+pub enum ReviewResult {
+    Easy, Good, Hard, Again
+}
+
+impl ReviewResult {
+    // This is a label from the `review_result` dictionary.
+    pub fn label(&self) -> &'static str {
+        match self {
+            ReviewResult::Easy => "easy",
+            ReviewResult::Good => "good",
+            ReviewResult::Hard => "hard",
+            ReviewResult::Again => "again",
+        }
+    }
+
+    pub fn from_label(label: &str) -> Result<Self> {
+        match label {
+            "easy" => Ok(ReviewResult::Easy),
+            "good" => Ok(ReviewResult::Good),
+            "hard" => Ok(ReviewResult::Hard),
+            "again" => Ok(ReviewResult::Again),
+            _ => Err(anyhow!("Unknown review result `{}`", label)),
+        }
+    }
+}
+
+// A row from the `review` table.
+#[derive(Clone)]
+pub struct Review {
+    pub ctime: DateTime<Utc>,
+    pub result: ReviewResult,
+}
+
+// All algorithms show a forgotten note again at the end of the session.
+pub fn again_delay() -> Duration {
+    Duration::minutes(5)
+}
+
+pub trait Scheduler {
+    // Name and parameters are saved in `review.decision` to keep intervals
+    // explainable after switching algorithms.
+    fn name(&self) -> &'static str;
+    fn params(&self) -> serde_json::Value;
+
+    // Time of the next review of a note created at `ctime`.
+    // `history` is ordered by time and ends with the review just made.
+    fn next_review(
+        &self,
+        ctime: DateTime<Utc>,
+        history: &[Review]
+    ) -> DateTime<Utc>;
+}
+
+// Scheduler is selected in the config file:
+//
+//     [scheduler]
+//     algorithm = "sm2"
+//     initial_ease = 2.3
+//
+// Parameters that are not set explicitly have default values.
+#[derive(Deserialize)]
+#[serde(tag = "algorithm", rename_all = "lowercase")]
+pub enum SchedulerConfig {
+    Fibonacci(Fibonacci),
+    Sm2(Sm2),
+    Fsrs(Fsrs),
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig::Fibonacci(Fibonacci::default())
+    }
+}
+
+impl SchedulerConfig {
+    pub fn scheduler(&self) -> &dyn Scheduler {
+        match self {
+            SchedulerConfig::Fibonacci(s) => s,
+            SchedulerConfig::Sm2(s) => s,
+            SchedulerConfig::Fsrs(s) => s,
+        }
+    }
+}
+
+fn as_days(d: Duration) -> f64 {
+    d.num_milliseconds() as f64 / Duration::days(1).num_milliseconds() as f64
+}
+
+fn from_days(days: f64) -> Duration {
+    Duration::milliseconds(
+        (days * Duration::days(1).num_milliseconds() as f64).round() as i64)
+}
+
+// Fixed intervals (with saturation).
+// This is a port of `nextReviewInterval` from the web app (src/config.js),
+// so notes migrated from there keep the same review cadence.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fibonacci {
+    // Ladder of intervals in days.
+    #[serde(deserialize_with = "non_empty")]
+    pub intervals: Vec<i64>,
+}
+
+fn non_empty<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Vec<i64>, D::Error> {
+    let intervals = Vec::<i64>::deserialize(d)?;
+    if intervals.is_empty() {
+        return Err(serde::de::Error::custom("intervals must not be empty"));
+    }
+    Ok(intervals)
+}
+
+impl Default for Fibonacci {
+    fn default() -> Self {
+        Fibonacci { intervals: vec![1, 2, 3, 5, 8, 13, 21, 34, 55, 89, 144] }
+    }
+}
+
+impl Fibonacci {
+    // Interval until the next review given the interval we expected the note
+    // to be remembered for and the time actually passed since the last review.
+    pub fn next_interval(
+        &self,
+        expected: Duration,
+        actual: Duration,
+        res: &ReviewResult
+    ) -> Duration {
+        let intervals = &self.intervals;
+        match res {
+            ReviewResult::Hard => {
+                // FIXME: restart progress? I.e. return intervals[0].
+                let interval = as_days(actual.min(expected));
+                let i = intervals.iter().position(|&x| x as f64 >= interval);
+                match i {
+                    Some(i) if i > 0 => Duration::days(intervals[i - 1]),
+                    _ => Duration::days(intervals[0]),
+                }
+            },
+            // The web app had only "easy" and "hard" options and its "easy"
+            // actually means "good": move to the next interval.
+            ReviewResult::Easy | ReviewResult::Good => {
+                // Don't increase interval if actual interval is significantly
+                // shorter than the expected one.
+                if as_days(actual) < as_days(expected) * 0.6 {
+                    return expected;
+                }
+                let interval = as_days(actual.max(expected));
+                let i = intervals.iter().position(|&x| x as f64 > interval);
+                Duration::days(intervals[i.unwrap_or(intervals.len() - 1)])
+            },
+            // The web app had no such option.
+            ReviewResult::Again =>
+                again_delay(),
+        }
+    }
+}
+
+impl Scheduler for Fibonacci {
+    fn name(&self) -> &'static str { "fibonacci" }
+
+    fn params(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("Params are always serializable")
+    }
+
+    fn next_review(
+        &self,
+        ctime: DateTime<Utc>,
+        history: &[Review]
+    ) -> DateTime<Utc> {
+        // The first interval starts at the first review: a note may have
+        // been created long before it was added to the queue (e.g. imported
+        // from Anki).
+        let mut last = history.first().map_or(ctime, |r| r.ctime);
+        let mut interval = Duration::zero();
+        for r in history {
+            interval = self.next_interval(interval, r.ctime - last, &r.result);
+            last = r.ctime;
+        }
+        last + interval
+    }
+}
+
+// SuperMemo-2 algorithm.
+// See https://super-memory.com/english/ol/sm2.htm
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sm2 {
+    pub initial_ease: f64,
+    pub min_ease: f64,
+    // Intervals after the first and the second successful reviews, in days.
+    pub first_interval: f64,
+    pub second_interval: f64,
+}
+
+impl Default for Sm2 {
+    fn default() -> Self {
+        Sm2 {
+            initial_ease: 2.5,
+            min_ease: 1.3,
+            first_interval: 1.0,
+            second_interval: 6.0,
+        }
+    }
+}
+
+impl Sm2 {
+    // Response quality on the 0..5 scale of the original algorithm.
+    fn quality(res: &ReviewResult) -> f64 {
+        match res {
+            ReviewResult::Again => 1.0,
+            ReviewResult::Hard => 3.0,
+            ReviewResult::Good => 4.0,
+            ReviewResult::Easy => 5.0,
+        }
+    }
+}
+
+impl Scheduler for Sm2 {
+    fn name(&self) -> &'static str { "sm2" }
+
+    fn params(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("Params are always serializable")
+    }
+
+    fn next_review(
+        &self,
+        ctime: DateTime<Utc>,
+        history: &[Review]
+    ) -> DateTime<Utc> {
+        let mut ease = self.initial_ease;
+        let mut repetitions = 0;
+        let mut interval = 0.0;
+        for r in history {
+            let q = Sm2::quality(&r.result);
+            if q < 3.0 {
+                repetitions = 0;
+            } else {
+                repetitions += 1;
+                interval = match repetitions {
+                    1 => self.first_interval,
+                    2 => self.second_interval,
+                    _ => (interval * ease).round(),
+                };
+            }
+            ease += 0.1 - (5.0 - q) * (0.08 + (5.0 - q) * 0.02);
+            ease = ease.max(self.min_ease);
+        }
+
+        match history.last() {
+            None => ctime,
+            Some(r) if repetitions == 0 => r.ctime + again_delay(),
+            Some(r) => r.ctime + from_days(interval),
+        }
+    }
+}
+
+// Free Spaced Repetition Scheduler (FSRS-4.5).
+// See https://github.com/open-spaced-repetition/fsrs4anki/wiki/The-Algorithm
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fsrs {
+    pub weights: Vec<f64>,
+    // Probability of recall we want to have at the time of the next review.
+    pub desired_retention: f64,
+    // Upper limit for the interval, in days.
+    pub max_interval: f64,
+}
+
+impl Default for Fsrs {
+    fn default() -> Self {
+        Fsrs {
+            weights: vec![
+                0.4872, 1.4003, 3.7145, 13.8206, 5.1618, 1.2298, 0.8975,
+                0.031, 1.6474, 0.1367, 1.0461, 2.1072, 0.0793, 0.3246,
+                1.587, 0.2272, 2.8755,
+            ],
+            desired_retention: 0.9,
+            max_interval: 36500.0,
+        }
+    }
+}
+
+const FSRS_DECAY: f64 = -0.5;
+const FSRS_FACTOR: f64 = 19.0 / 81.0;
+
+impl Fsrs {
+    // FSRS grades are 1..4 (Again, Hard, Good, Easy).
+    fn grade(res: &ReviewResult) -> f64 {
+        match res {
+            ReviewResult::Again => 1.0,
+            ReviewResult::Hard => 2.0,
+            ReviewResult::Good => 3.0,
+            ReviewResult::Easy => 4.0,
+        }
+    }
+
+    fn w(&self, i: usize) -> f64 {
+        self.weights.get(i).copied()
+            .unwrap_or_else(|| Fsrs::default().weights[i])
+    }
+
+    fn initial_difficulty(&self, g: f64) -> f64 {
+        (self.w(4) - (g - 3.0) * self.w(5)).clamp(1.0, 10.0)
+    }
+
+    fn retrievability(elapsed_days: f64, stability: f64) -> f64 {
+        (1.0 + FSRS_FACTOR * elapsed_days / stability).powf(FSRS_DECAY)
+    }
+
+    fn interval(&self, stability: f64) -> f64 {
+        let i = stability / FSRS_FACTOR
+            * (self.desired_retention.powf(1.0 / FSRS_DECAY) - 1.0);
+        i.round().clamp(1.0, self.max_interval)
+    }
+
+    // Returns (stability, difficulty) after the review with grade `g`.
+    fn update(&self, s: f64, d: f64, elapsed_days: f64, g: f64) -> (f64, f64) {
+        let r = Fsrs::retrievability(elapsed_days, s);
+        let s = if g == 1.0 {
+            self.w(11) * d.powf(-self.w(12)) * ((s + 1.0).powf(self.w(13)) - 1.0)
+                * (self.w(14) * (1.0 - r)).exp()
+        } else {
+            let hard_penalty = if g == 2.0 { self.w(15) } else { 1.0 };
+            let easy_bonus = if g == 4.0 { self.w(16) } else { 1.0 };
+            s * (self.w(8).exp() * (11.0 - d) * s.powf(-self.w(9))
+                * ((self.w(10) * (1.0 - r)).exp() - 1.0)
+                * hard_penalty * easy_bonus + 1.0)
+        };
+        let d = d - self.w(6) * (g - 3.0);
+        let d = self.w(7) * self.initial_difficulty(4.0) + (1.0 - self.w(7)) * d;
+        (s, d.clamp(1.0, 10.0))
+    }
+}
+
+impl Scheduler for Fsrs {
+    fn name(&self) -> &'static str { "fsrs" }
+
+    fn params(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("Params are always serializable")
+    }
+
+    fn next_review(
+        &self,
+        ctime: DateTime<Utc>,
+        history: &[Review]
+    ) -> DateTime<Utc> {
+        let Some((first, rest)) = history.split_first() else {
+            return ctime;
+        };
+        let g = Fsrs::grade(&first.result);
+        let mut s = self.w(g as usize - 1);
+        let mut d = self.initial_difficulty(g);
+        let mut last = first;
+        for r in rest {
+            let elapsed = as_days(r.ctime - last.ctime);
+            (s, d) = self.update(s, d, elapsed, Fsrs::grade(&r.result));
+            last = r;
+        }
+
+        match last.result {
+            ReviewResult::Again => last.ctime + again_delay(),
+            _ => last.ctime + from_days(self.interval(s)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ported from src/config.test.js
+    fn t(expected: Duration, actual: Duration, res: ReviewResult, next: i64) {
+        let interval = Fibonacci::default().next_interval(expected, actual, &res);
+        assert_eq!(interval, Duration::days(next));
+    }
+
+    fn hours(h: i64) -> Duration { Duration::hours(h) }
+    fn days(d: i64) -> Duration { Duration::days(d) }
+
+    #[test]
+    fn first_review() {
+        t(days(0), hours(1), ReviewResult::Easy, 1);
+        t(days(0), hours(1), ReviewResult::Hard, 1);
+    }
+
+    #[test]
+    fn first_review_after_a_long_time() {
+        t(days(0), days(300), ReviewResult::Easy, 144);
+        t(days(0), days(300), ReviewResult::Hard, 1);
+    }
+
+    #[test]
+    fn increase_and_decrease_interval() {
+        t(days(3), days(3) + hours(12), ReviewResult::Easy, 5);
+        t(days(3), days(3) + hours(12), ReviewResult::Hard, 2);
+    }
+
+    #[test]
+    fn saturation() {
+        t(days(144), days(144) + hours(12), ReviewResult::Easy, 144);
+        t(days(1), days(1) + hours(12), ReviewResult::Hard, 1);
+    }
+
+    #[test]
+    fn premature_review() {
+        t(days(5), days(1), ReviewResult::Easy, 5);
+        t(days(5), days(1), ReviewResult::Hard, 1);
+        t(days(5), days(2) + hours(2), ReviewResult::Hard, 2);
+    }
+
+    #[test]
+    fn again_is_not_measured_in_days() {
+        let interval = Fibonacci::default().next_interval(
+            days(5), days(5), &ReviewResult::Again);
+        assert_eq!(interval, Duration::minutes(5));
+    }
+
+    // Reviews made on time, `offsets` are days since the note creation.
+    fn history(offsets: &[(i64, ReviewResult)]) -> (DateTime<Utc>, Vec<Review>) {
+        let ctime = Utc::now();
+        let reviews = offsets.iter()
+            .map(|(d, result)| Review { ctime: ctime + days(*d), result: *result })
+            .collect();
+        (ctime, reviews)
+    }
+
+    fn next_in_days(s: &dyn Scheduler, offsets: &[(i64, ReviewResult)]) -> f64 {
+        let (ctime, reviews) = history(offsets);
+        let last = reviews.last().map_or(ctime, |r| r.ctime);
+        as_days(s.next_review(ctime, &reviews) - last)
+    }
+
+    #[test]
+    fn fibonacci_replays_history() {
+        use ReviewResult::*;
+        let s = Fibonacci::default();
+        assert_eq!(next_in_days(&s, &[(0, Easy)]), 1.0);
+        assert_eq!(next_in_days(&s, &[(0, Easy), (1, Easy)]), 2.0);
+        assert_eq!(next_in_days(&s, &[(0, Easy), (1, Easy), (3, Easy)]), 3.0);
+        assert_eq!(next_in_days(&s, &[(0, Easy), (1, Easy), (3, Hard)]), 1.0);
+        assert_eq!(next_in_days(&s, &[(0, Good), (1, Good), (3, Good)]), 3.0);
+    }
+
+    #[test]
+    fn fibonacci_ignores_note_age() {
+        use ReviewResult::*;
+        let s = Fibonacci::default();
+        let ctime = Utc::now() - days(1000);
+        let first = Review { ctime: Utc::now(), result: Easy };
+        assert_eq!(s.next_review(ctime, std::slice::from_ref(&first)), first.ctime + days(1));
+        let second = Review { ctime: first.ctime + days(1), result: Easy };
+        assert_eq!(s.next_review(ctime, &[first, second.clone()]), second.ctime + days(2));
+    }
+
+    #[test]
+    fn sm2_intervals() {
+        use ReviewResult::*;
+        let s = Sm2::default();
+        assert_eq!(next_in_days(&s, &[(0, Easy)]), 1.0);
+        assert_eq!(next_in_days(&s, &[(0, Easy), (1, Easy)]), 6.0);
+        // ease grows by 0.1 on each easy review: 6 * 2.7 = 16.2
+        assert_eq!(next_in_days(&s, &[(0, Easy), (1, Easy), (7, Easy)]), 16.0);
+        // hard reviews decrease ease: 6 * (2.5 - 2 * 0.14) = 13.32
+        assert_eq!(next_in_days(&s, &[(0, Hard), (1, Hard), (7, Hard)]), 13.0);
+        // good reviews keep ease unchanged
+        assert_eq!(next_in_days(&s, &[(0, Good), (1, Good), (7, Good)]), 15.0);
+        assert_eq!(
+            next_in_days(&s, &[(0, Easy), (1, Easy), (7, Again)]),
+            as_days(again_delay()));
+    }
+
+    #[test]
+    fn fsrs_intervals() {
+        use ReviewResult::*;
+        let s = Fsrs::default();
+        // initial stability is the interval for the desired retention of 90%
+        assert_eq!(next_in_days(&s, &[(0, Hard)]), 1.0);
+        assert_eq!(next_in_days(&s, &[(0, Good)]), 4.0);
+        assert_eq!(next_in_days(&s, &[(0, Easy)]), 14.0);
+        let easy = next_in_days(&s, &[(0, Easy), (14, Easy)]);
+        let hard = next_in_days(&s, &[(0, Easy), (14, Hard)]);
+        assert!(easy > hard && hard > 14.0);
+        assert_eq!(
+            next_in_days(&s, &[(0, Easy), (14, Again)]),
+            as_days(again_delay()));
+        let relearned = next_in_days(&s, &[(0, Easy), (14, Again), (14, Easy)]);
+        assert!(relearned < easy);
+    }
+
+    #[test]
+    fn parse_scheduler_config() -> Result<()> {
+        let cfg: SchedulerConfig = serde_json::from_str(
+            r#"{"algorithm": "sm2", "initial_ease": 2.3}"#)?;
+        let s = cfg.scheduler();
+        assert_eq!(s.name(), "sm2");
+        assert_eq!(s.params()["initial_ease"], 2.3);
+        assert_eq!(s.params()["min_ease"], 1.3);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_empty_intervals() -> Result<()> {
+        let cfg: SchedulerConfig = serde_json::from_str(
+            r#"{"algorithm": "fibonacci", "intervals": [1, 3]}"#)?;
+        assert_eq!(cfg.scheduler().params()["intervals"], serde_json::json!([1, 3]));
+        let cfg = serde_json::from_str::<SchedulerConfig>(
+            r#"{"algorithm": "fibonacci", "intervals": []}"#);
+        assert!(cfg.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,545 @@
+// Two-way sync between CLI databases (`fhmp sync`).
+//
+// Note versions are added by edits, and their status is changed e.g. by
+// `fhmp suspend` or `fhmp conflicts`. Every insert and status change gives
+// the version the next number of the change sequence (`notes.seq`). Reviews
+// are append-only and numbered by their ids. The max numbers already
+// exchanged with each remote are kept in `sync_state`.
+//
+// The local side sends its changes and the max numbers of the remote it has
+// seen. The remote applies the changes and replies with its own ones.
+// Both sides merge changes idempotently:
+//   - note versions are identified by `hash`, versions edited concurrently on
+//     both sides become conflicts (see `db::insert_note_row`);
+//   - of two status changes the later one wins, by the time of the change
+//     (see `db::merge_note_state`);
+//   - reviews are identified by note, time and result.
+// Queue entries of affected notes are recomputed from the merged history.
+use std::collections::{BTreeSet, HashMap, HashSet};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::config::CliConfig;
+use crate::db::{self, Inserted, NoteRow};
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+#[cfg_attr(test, derive(PartialEq, Debug))]
+pub struct Watermark {
+    pub notes: i64,
+    pub reviews: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReviewRow {
+    pub note_id: Uuid,
+    pub ctime: DateTime<Utc>,
+    pub result: String,
+    pub decision: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Changeset {
+    pub notes: Vec<NoteRow>,
+    pub reviews: Vec<ReviewRow>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SyncRequest {
+    // Remote changes up to these numbers are already known to the local side.
+    pub since: Watermark,
+    pub changes: Changeset,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SyncResponse {
+    pub changes: Changeset,
+    pub watermark: Watermark,
+}
+
+#[derive(Default)]
+#[cfg_attr(test, derive(PartialEq, Debug))]
+pub struct SyncStats {
+    pub sent_notes: usize,
+    pub sent_reviews: usize,
+    // Rows that were not known yet.
+    pub received_notes: usize,
+    pub received_reviews: usize,
+    // Known versions with a newer status.
+    pub updated_notes: usize,
+    // Received versions that conflict with local edits.
+    pub conflicts: usize,
+}
+
+#[derive(Default)]
+struct ApplyStats {
+    notes: usize,
+    updated_notes: usize,
+    reviews: usize,
+    conflicts: usize,
+}
+
+// Remote side of the sync: applies changes from the local side and returns
+// its own changes. Used for file remotes and by `fhmp serve`.
+pub fn exchange(db: &Connection, cfg: &CliConfig, req: &SyncRequest) -> Result<SyncResponse> {
+    let tx = db.unchecked_transaction()?;
+    // Changes are collected before applying the request to not send the
+    // same rows back.
+    let changes = changes_since(&tx, req.since)?;
+    apply(&tx, cfg, &req.changes)?;
+    let watermark = watermark(&tx)?;
+    tx.commit()?;
+    Ok(SyncResponse { changes, watermark })
+}
+
+// Local side of the sync. `remote` is a name to keep the watermarks and
+// `send` delivers the request to the remote.
+pub fn sync<F>(db: &Connection, cfg: &CliConfig, remote: &str, send: F) -> Result<SyncStats>
+where
+    F: FnOnce(&SyncRequest) -> Result<SyncResponse>
+{
+    let (pulled, pushed) = load_state(db, remote)?;
+    let req = SyncRequest {
+        since: pulled,
+        changes: changes_since(db, pushed)?,
+    };
+    let rsp = send(&req)?;
+
+    let tx = db.unchecked_transaction()?;
+    let received = apply(&tx, cfg, &rsp.changes)?;
+    // Received rows are known to the remote and must not be sent back.
+    save_state(&tx, remote, rsp.watermark, watermark(&tx)?)?;
+    tx.commit()?;
+
+    Ok(SyncStats {
+        sent_notes: req.changes.notes.len(),
+        sent_reviews: req.changes.reviews.len(),
+        received_notes: received.notes,
+        received_reviews: received.reviews,
+        updated_notes: received.updated_notes,
+        conflicts: received.conflicts,
+    })
+}
+
+fn watermark(db: &Connection) -> Result<Watermark> {
+    let w = db.query_row("
+        select
+            (select coalesce(max(seq), 0) from notes),
+            (select coalesce(max(id), 0) from review)
+        ",
+        [],
+        |row| Ok(Watermark { notes: row.get(0)?, reviews: row.get(1)? }))?;
+    Ok(w)
+}
+
+// Returns (pulled, pushed) watermarks.
+fn load_state(db: &Connection, remote: &str) -> Result<(Watermark, Watermark)> {
+    let state = db.query_row("
+        select pulled_notes, pulled_reviews, pushed_notes, pushed_reviews
+        from sync_state
+        where remote = ?
+        ",
+        [remote],
+        |row| Ok((
+            Watermark { notes: row.get(0)?, reviews: row.get(1)? },
+            Watermark { notes: row.get(2)?, reviews: row.get(3)? },
+        ))).optional()?;
+    Ok(state.unwrap_or_default())
+}
+
+fn save_state(db: &Connection, remote: &str, pulled: Watermark, pushed: Watermark) -> Result<()> {
+    db.execute("
+        insert or replace into sync_state
+          (remote, pulled_notes, pulled_reviews, pushed_notes, pushed_reviews)
+        values
+          (?, ?, ?, ?, ?)
+        ",
+        params![remote, pulled.notes, pulled.reviews, pushed.notes, pushed.reviews])?;
+    Ok(())
+}
+
+fn changes_since(db: &Connection, since: Watermark) -> Result<Changeset> {
+    let mut q = db.prepare("
+        select hash, uuid, ctime, mtime, tags, data, parent, status, buried_until, stime
+        from notes
+        where seq > ?
+        order by seq
+    ")?;
+    let notes = q
+        .query_map([since.notes], |row| Ok(NoteRow {
+            hash: row.get(0)?,
+            uuid: row.get(1)?,
+            ctime: row.get(2)?,
+            mtime: row.get(3)?,
+            tags: row.get(4)?,
+            data: row.get(5)?,
+            parent: row.get(6)?,
+            status: row.get(7)?,
+            buried_until: row.get(8)?,
+            stime: row.get(9)?,
+        }))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut q = db.prepare("
+        select r.note_id, r.ctime, rr.label, r.decision
+        from review r, review_result rr
+        where r.id > ?
+          and rr.id = r.result
+        order by r.id
+    ")?;
+    let mut rows = q.query([since.reviews])?;
+    let mut reviews = Vec::new();
+    while let Some(row) = rows.next()? {
+        reviews.push(ReviewRow {
+            note_id: Uuid::parse_str(&row.get::<_, String>(0)?)?,
+            ctime: DateTime::parse_from_rfc3339(&row.get::<_, String>(1)?)?
+                .with_timezone(&Utc),
+            result: row.get(2)?,
+            decision: serde_json::from_str(&row.get::<_, String>(3)?)?,
+        });
+    }
+    Ok(Changeset { notes, reviews })
+}
+
+// Returns stats of received rows.
+fn apply(db: &Connection, cfg: &CliConfig, changes: &Changeset) -> Result<ApplyStats> {
+    let mut stats = ApplyStats::default();
+    let mut affected = BTreeSet::new();
+    let mut new = HashMap::new();
+    let mut known = Vec::new();
+    for n in changes.notes.iter() {
+        if db::note_status(db, &n.hash)?.is_some() {
+            known.push(n);
+        } else {
+            new.insert(n.hash.as_str(), n);
+        }
+    }
+
+    // Parents go before their children. Changes are ordered by the sequence,
+    // but a parent is numbered again when it is retired.
+    let mut inserted = HashSet::new();
+    for n in changes.notes.iter().filter(|n| new.contains_key(n.hash.as_str())) {
+        let mut chain = vec![n];
+        while let Some(p) = chain[chain.len() - 1].parent.as_deref()
+            .and_then(|p| new.get(p))
+            .filter(|p| !inserted.contains(p.hash.as_str()))
+        {
+            chain.push(p);
+        }
+        for n in chain.into_iter().rev() {
+            if !inserted.insert(n.hash.as_str()) {
+                continue;
+            }
+            match db::insert_note_row(db, n)? {
+                Inserted::Exists => continue,
+                Inserted::New => {},
+                Inserted::Conflict => stats.conflicts += 1,
+            }
+            affected.insert(Uuid::parse_str(&n.uuid)?);
+            stats.notes += 1;
+        }
+    }
+
+    for n in known {
+        if db::merge_note_state(db, n)? {
+            affected.insert(Uuid::parse_str(&n.uuid)?);
+            stats.updated_notes += 1;
+        }
+    }
+
+    for r in changes.reviews.iter() {
+        if db::insert_review(db, &r.note_id, &r.ctime, &r.result, &r.decision)? {
+            affected.insert(r.note_id);
+            stats.reviews += 1;
+        }
+    }
+
+    for uuid in affected.iter() {
+        reschedule(db, cfg, uuid)?;
+    }
+    Ok(stats)
+}
+
+// Recomputes the next review from the history, e.g. after merging reviews
+// made elsewhere. Notes that were never reviewed stay in the queue as they are.
+pub fn reschedule(db: &Connection, cfg: &CliConfig, uuid: &Uuid) -> Result<()> {
+    let Some(note) = db::current_note(db, uuid)? else {
+        return Ok(());
+    };
+    let history = db::review_history(db, uuid)?;
+    if !history.is_empty() {
+        db::set_next_review(db, uuid, &cfg.next_review(&note, &history))?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use crate::note::{DbNote, NoteData};
+
+    fn test_config() -> CliConfig {
+        config::Config::builder()
+            .add_source(config::File::from_str(
+                "db_path = \"\"\ndata_path = \"\"",
+                config::FileFormat::Toml))
+            .build()
+            .and_then(|c| c.try_deserialize())
+            .expect("valid config")
+    }
+
+    fn test_db() -> Result<Connection> {
+        let db = db::open(":memory:")?;
+        db::init_schema(&db)?;
+        Ok(db)
+    }
+
+    fn text_note(uuid: Uuid, text: &str) -> DbNote {
+        DbNote {
+            uuid,
+            ctime: Utc::now() - Duration::days(10),
+            tags: "sync".to_string(),
+            data: NoteData::Text(text.to_string()),
+            parent: None,
+        }
+    }
+
+    fn sync_dbs(cfg: &CliConfig, local: &Connection, remote: &Connection) -> Result<SyncStats> {
+        sync(local, cfg, "remote", |req| exchange(remote, cfg, req))
+    }
+
+    #[test]
+    fn two_way_sync() -> Result<()> {
+        let cfg = test_config();
+        let (laptop, desktop) = (test_db()?, test_db()?);
+
+        let n1 = text_note(Uuid::new_v4(), "one");
+        db::insert_notes(&laptop, std::slice::from_ref(&n1))?;
+        let n2 = text_note(Uuid::new_v4(), "two");
+        db::insert_notes(&desktop, std::slice::from_ref(&n2))?;
+
+        let stats = sync_dbs(&cfg, &laptop, &desktop)?;
+        assert_eq!((stats.sent_notes, stats.received_notes), (1, 1));
+        // Nothing new on both sides.
+        assert_eq!(sync_dbs(&cfg, &laptop, &desktop)?, SyncStats::default());
+
+        // Update on the desktop and review on the laptop.
+        let n1v2 = text_note(n1.uuid, "one, updated");
+        db::insert_notes(&desktop, std::slice::from_ref(&n1v2))?;
+        let now = Utc::now();
+        db::save_review(&laptop, &n1.uuid, &now, "good", &serde_json::json!({}), &now)?;
+
+        let stats = sync_dbs(&cfg, &laptop, &desktop)?;
+        assert_eq!(stats.sent_reviews, 1);
+        assert_eq!(stats.received_notes, 1);
+        for db in [&laptop, &desktop] {
+            assert_eq!(db::current_note(db, &n1.uuid)?, Some(n1v2.clone()));
+            assert_eq!(db::review_history(db, &n1.uuid)?.len(), 1);
+            // Queue is recomputed from the history on both sides.
+            let entry = db::queue_entry(db, &n1.uuid)?;
+            let expected = cfg.next_review(&n1v2, &db::review_history(db, &n1.uuid)?);
+            assert_eq!(db::to_db_time(&entry.next_review), db::to_db_time(&expected));
+        }
+        assert_eq!(sync_dbs(&cfg, &laptop, &desktop)?, SyncStats::default());
+        Ok(())
+    }
+
+    #[test]
+    fn retired_versions_do_not_retire_current() -> Result<()> {
+        let cfg = test_config();
+        let (laptop, desktop) = (test_db()?, test_db()?);
+        let uuid = Uuid::new_v4();
+        let v1 = text_note(uuid, "v1");
+        let v2 = text_note(uuid, "v2");
+        db::insert_notes(&laptop, &[v1, v2.clone()])?;
+
+        sync_dbs(&cfg, &laptop, &desktop)?;
+        assert_eq!(db::current_note(&desktop, &uuid)?, Some(v2.clone()));
+        // The same rows come back from a third database.
+        let third = test_db()?;
+        sync(&third, &cfg, "desktop", |req| exchange(&desktop, &cfg, req))?;
+        sync(&third, &cfg, "laptop", |req| exchange(&laptop, &cfg, req))?;
+        assert_eq!(db::current_note(&third, &uuid)?, Some(v2.clone()));
+        assert_eq!(db::current_note(&laptop, &uuid)?, Some(v2));
+        Ok(())
+    }
+
+    #[test]
+    fn reverts_are_synced() -> Result<()> {
+        let cfg = test_config();
+        let (laptop, desktop) = (test_db()?, test_db()?);
+        let uuid = Uuid::new_v4();
+        let v1 = text_note(uuid, "v1");
+        db::insert_notes(&laptop, &[v1.clone(), text_note(uuid, "v2")])?;
+        sync_dbs(&cfg, &laptop, &desktop)?;
+
+        db::revert_note(&laptop, &uuid, &v1.hash_and_json().0)?;
+        let stats = sync_dbs(&cfg, &laptop, &desktop)?;
+        assert_eq!((stats.sent_notes, stats.conflicts), (2, 0));
+        for db in [&laptop, &desktop] {
+            assert_eq!(db::current_note(db, &uuid)?, Some(v1.clone()));
+        }
+        assert_eq!(sync_dbs(&cfg, &laptop, &desktop)?, SyncStats::default());
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_edits_conflict() -> Result<()> {
+        let cfg = test_config();
+        let (laptop, desktop) = (test_db()?, test_db()?);
+        let uuid = Uuid::new_v4();
+        let v1 = text_note(uuid, "v1");
+        db::insert_notes(&laptop, std::slice::from_ref(&v1))?;
+        sync_dbs(&cfg, &laptop, &desktop)?;
+
+        let v2 = text_note(uuid, "laptop");
+        db::insert_notes(&laptop, std::slice::from_ref(&v2))?;
+        let v3 = text_note(uuid, "desktop");
+        db::insert_notes(&desktop, std::slice::from_ref(&v3))?;
+        assert_eq!(sync_dbs(&cfg, &laptop, &desktop)?.conflicts, 1);
+        // Both sides keep their own version.
+        assert_eq!(db::current_note(&laptop, &uuid)?, Some(v2.clone()));
+        assert_eq!(db::current_note(&desktop, &uuid)?, Some(v3.clone()));
+        assert_eq!(db::conflicting_notes(&laptop)?, vec![v3]);
+        assert_eq!(db::conflicting_notes(&desktop)?, vec![v2.clone()]);
+
+        // Merged on the laptop.
+        let merged = DbNote { parent: Some(v2.hash_and_json().0), ..text_note(uuid, "merged") };
+        db::insert_notes(&laptop, std::slice::from_ref(&merged))?;
+        db::resolve_conflict(&laptop, &uuid, &merged.hash_and_json().0)?;
+        sync_dbs(&cfg, &laptop, &desktop)?;
+        let merged = DbNote { parent: None, ..merged };
+        for db in [&laptop, &desktop] {
+            assert_eq!(db::current_note(db, &uuid)?, Some(merged.clone()));
+            assert!(db::conflicting_notes(db)?.is_empty());
+        }
+        Ok(())
+    }
+
+    fn current_status(db: &Connection, uuid: &Uuid) -> Result<Option<i64>> {
+        let status = db.query_row(
+            "select status from notes where uuid = ? and status in (1, 5, 6)",
+            [uuid.to_string()],
+            |row| row.get(0)).optional()?;
+        Ok(status)
+    }
+
+    // Status changes made in the same millisecond would be equal.
+    fn backdate(db: &Connection, uuid: &Uuid) -> Result<()> {
+        db.execute("
+            update notes
+                set stime = '2000-01-01T00:00:00.000Z'
+                where uuid = ? and stime is not null
+            ",
+            [uuid.to_string()])?;
+        Ok(())
+    }
+
+    #[test]
+    fn status_changes_are_synced() -> Result<()> {
+        let cfg = test_config();
+        let (laptop, desktop) = (test_db()?, test_db()?);
+        let uuid = Uuid::new_v4();
+        db::insert_notes(&laptop, &[text_note(uuid, "v1")])?;
+        sync_dbs(&cfg, &laptop, &desktop)?;
+
+        db::set_note_status(&laptop, &uuid, &db::NoteStatus::Suspended)?;
+        backdate(&laptop, &uuid)?;
+        let stats = sync_dbs(&cfg, &laptop, &desktop)?;
+        assert_eq!((stats.sent_notes, stats.updated_notes), (1, 0));
+        assert_eq!(current_status(&desktop, &uuid)?, Some(5));
+        assert_eq!(sync_dbs(&cfg, &laptop, &desktop)?, SyncStats::default());
+
+        // The later change wins.
+        db::set_note_status(&desktop, &uuid, &db::NoteStatus::Active)?;
+        let stats = sync_dbs(&cfg, &laptop, &desktop)?;
+        assert_eq!(stats.updated_notes, 1);
+        assert_eq!(current_status(&laptop, &uuid)?, Some(1));
+        assert_eq!(sync_dbs(&cfg, &laptop, &desktop)?, SyncStats::default());
+
+        // A new database gets the current status.
+        db::set_note_status(&desktop, &uuid, &db::NoteStatus::Suspended)?;
+        let third = test_db()?;
+        sync(&third, &cfg, "desktop", |req| exchange(&desktop, &cfg, req))?;
+        assert_eq!(current_status(&third, &uuid)?, Some(5));
+        Ok(())
+    }
+
+    #[test]
+    fn deletions_are_synced() -> Result<()> {
+        let cfg = test_config();
+        let (laptop, desktop) = (test_db()?, test_db()?);
+        let uuid = Uuid::new_v4();
+        let v1 = text_note(uuid, "v1");
+        db::insert_notes(&laptop, std::slice::from_ref(&v1))?;
+        sync_dbs(&cfg, &laptop, &desktop)?;
+
+        // Edited on the desktop after it was deleted on the laptop.
+        db::set_note_status(&laptop, &uuid, &db::NoteStatus::Deleted)?;
+        db::insert_notes(&desktop, &[text_note(uuid, "v2")])?;
+        sync_dbs(&cfg, &laptop, &desktop)?;
+        for db in [&laptop, &desktop] {
+            assert_eq!(db::current_note(db, &uuid)?, None);
+        }
+        assert_eq!(sync_dbs(&cfg, &laptop, &desktop)?, SyncStats::default());
+
+        // Revert brings it back everywhere.
+        db::revert_note(&desktop, &uuid, &v1.hash_and_json().0)?;
+        sync_dbs(&cfg, &laptop, &desktop)?;
+        for db in [&laptop, &desktop] {
+            assert_eq!(db::current_note(db, &uuid)?, Some(v1.clone()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn status_of_edited_notes_is_synced() -> Result<()> {
+        let cfg = test_config();
+        let (laptop, desktop) = (test_db()?, test_db()?);
+        let uuid = Uuid::new_v4();
+        db::insert_notes(&laptop, &[text_note(uuid, "v1")])?;
+        sync_dbs(&cfg, &laptop, &desktop)?;
+
+        // Suspended on the laptop before it was edited on the desktop.
+        db::set_note_status(&laptop, &uuid, &db::NoteStatus::Suspended)?;
+        backdate(&laptop, &uuid)?;
+        let v2 = text_note(uuid, "v2");
+        db::insert_notes(&desktop, std::slice::from_ref(&v2))?;
+        let stats = sync_dbs(&cfg, &laptop, &desktop)?;
+        assert_eq!((stats.received_notes, stats.conflicts), (1, 0));
+        for db in [&laptop, &desktop] {
+            assert_eq!(db::current_note(db, &uuid)?, Some(v2.clone()));
+            assert_eq!(current_status(db, &uuid)?, Some(5));
+        }
+        assert_eq!(sync_dbs(&cfg, &laptop, &desktop)?, SyncStats::default());
+        Ok(())
+    }
+
+    #[test]
+    fn chosen_versions_are_synced() -> Result<()> {
+        let cfg = test_config();
+        let (laptop, desktop) = (test_db()?, test_db()?);
+        let uuid = Uuid::new_v4();
+        db::insert_notes(&laptop, &[text_note(uuid, "v1")])?;
+        sync_dbs(&cfg, &laptop, &desktop)?;
+        let v2 = text_note(uuid, "laptop");
+        db::insert_notes(&laptop, std::slice::from_ref(&v2))?;
+        let v3 = text_note(uuid, "desktop");
+        db::insert_notes(&desktop, std::slice::from_ref(&v3))?;
+        sync_dbs(&cfg, &laptop, &desktop)?;
+
+        // The desktop version is chosen on the laptop.
+        db::resolve_conflict(&laptop, &uuid, &v3.hash_and_json().0)?;
+        let stats = sync_dbs(&cfg, &laptop, &desktop)?;
+        assert_eq!(stats.sent_notes, 2);
+        for db in [&laptop, &desktop] {
+            assert_eq!(db::current_note(db, &uuid)?, Some(v3.clone()));
+            assert!(db::conflicting_notes(db)?.is_empty());
+        }
+        assert_eq!(sync_dbs(&cfg, &laptop, &desktop)?, SyncStats::default());
+        Ok(())
+    }
+}
@@ -0,0 +1,354 @@
+// Notes in CSV and TSV format, one note per row, for editing in spreadsheets.
+//
+// A row is a card with `front` and `back` columns, or a text note if its
+// `text` column is not empty. Sides of a card after the second one are kept
+// in the `back` column separated by a `----` line, a line of a side that
+// looks like the separator is escaped with a backslash, e.g. `\----`. Tags
+// are separated by commas, like in YAML notes.
+use std::io;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::DateTime;
+use uuid::Uuid;
+use crate::note::{DbNote, InputNote, NoteData};
+
+const SIDE_SEPARATOR: &str = "----";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Column {
+    Uuid,
+    Ctime,
+    Tags,
+    Front,
+    Back,
+    Text,
+}
+
+const ALL_COLUMNS: [Column; 6] = [
+    Column::Uuid,
+    Column::Ctime,
+    Column::Tags,
+    Column::Front,
+    Column::Back,
+    Column::Text,
+];
+
+impl Column {
+    fn parse(s: &str) -> Result<Column> {
+        match s.trim().to_lowercase().as_str() {
+            "uuid" => Ok(Column::Uuid),
+            "ctime" => Ok(Column::Ctime),
+            "tags" => Ok(Column::Tags),
+            "front" => Ok(Column::Front),
+            "back" => Ok(Column::Back),
+            "text" => Ok(Column::Text),
+            _ => bail!("Unknown column \"{}\", expected one of \
+                uuid, ctime, tags, front, back, text", s),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Column::Uuid => "uuid",
+            Column::Ctime => "ctime",
+            Column::Tags => "tags",
+            Column::Front => "front",
+            Column::Back => "back",
+            Column::Text => "text",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Yaml,
+    Markdown,
+    Csv,
+    Tsv,
+}
+
+#[derive(Debug)]
+pub struct TableFormat {
+    pub format: Format,
+    // Columns of rows without a header. The first row is the header
+    // naming the columns if it is not set.
+    pub columns: Option<Vec<Column>>,
+    // None disables quoting.
+    pub quote: Option<u8>,
+}
+
+impl TableFormat {
+    // Leading `--format`, `--columns`, `--quote` and `--no-quote` options
+    // are parsed, the rest of the arguments is returned.
+    pub fn from_args(mut args: &[String]) -> Result<(TableFormat, &[String])> {
+        let mut fmt = TableFormat {
+            format: Format::Yaml,
+            columns: None,
+            quote: Some(b'"'),
+        };
+        loop {
+            match args {
+                [opt, val, rest @ ..] if opt == "--format" => {
+                    fmt.format = match val.as_str() {
+                        "yaml" => Format::Yaml,
+                        "md" => Format::Markdown,
+                        "csv" => Format::Csv,
+                        "tsv" => Format::Tsv,
+                        _ => bail!("Unknown format \"{}\", expected yaml, md, csv or tsv", val),
+                    };
+                    args = rest;
+                }
+                [opt, val, rest @ ..] if opt == "--columns" => {
+                    fmt.columns = Some(val.split(',')
+                        .map(Column::parse)
+                        .collect::<Result<_>>()?);
+                    args = rest;
+                }
+                [opt, val, rest @ ..] if opt == "--quote" => {
+                    fmt.quote = match val.as_bytes() {
+                        [q] => Some(*q),
+                        _ => bail!("Quote must be a single ASCII character"),
+                    };
+                    args = rest;
+                }
+                [opt, rest @ ..] if opt == "--no-quote" => {
+                    fmt.quote = None;
+                    args = rest;
+                }
+                [opt, ..] if opt.starts_with("--") =>
+                    bail!("Unknown option {}", opt),
+                _ => break,
+            }
+        }
+        let is_table = matches!(fmt.format, Format::Csv | Format::Tsv);
+        if !is_table && (fmt.columns.is_some() || fmt.quote != Some(b'"')) {
+            bail!("Columns and quoting are options of csv and tsv formats");
+        }
+        Ok((fmt, args))
+    }
+
+    fn delimiter(&self) -> u8 {
+        match self.format {
+            Format::Tsv => b'\t',
+            _ => b',',
+        }
+    }
+}
+
+pub fn read_notes<R: io::Read>(r: R, fmt: &TableFormat) -> Result<Vec<InputNote>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(fmt.delimiter())
+        .quote(fmt.quote.unwrap_or(b'"'))
+        .quoting(fmt.quote.is_some())
+        .has_headers(fmt.columns.is_none())
+        .flexible(true)
+        .from_reader(r);
+    let columns = match &fmt.columns {
+        Some(columns) => columns.clone(),
+        None => rdr.headers()?
+            .iter()
+            .map(Column::parse)
+            .collect::<Result<_>>()
+            .context("Parsing the header")?,
+    };
+
+    let mut notes = Vec::new();
+    for (i, record) in rdr.records().enumerate() {
+        let record = record?;
+        // Rows are numbered from 1, after the header if there is one.
+        let row = i + 1 + usize::from(fmt.columns.is_none());
+        let note = parse_row(&columns, &record)
+            .with_context(|| format!("Row {}", row))?;
+        notes.push(note);
+    }
+    Ok(notes)
+}
+
+fn parse_row(columns: &[Column], record: &csv::StringRecord) -> Result<InputNote> {
+    let mut note = InputNote {
+        uuid: None,
+        ctime: None,
+        tags: String::new(),
+        data: NoteData::Text(String::new()),
+        parent: None,
+    };
+    let (mut front, mut back, mut text) = ("", "", "");
+    for (col, val) in columns.iter().zip(record.iter()) {
+        match col {
+            Column::Uuid if !val.is_empty() =>
+                note.uuid = Some(Uuid::parse_str(val)?),
+            Column::Ctime if !val.is_empty() =>
+                note.ctime = Some(DateTime::parse_from_rfc3339(val)?.into()),
+            Column::Tags => note.tags = val.to_string(),
+            Column::Front => front = val,
+            Column::Back => back = val,
+            Column::Text => text = val,
+            _ => {}
+        }
+    }
+
+    note.data = if !text.is_empty() {
+        NoteData::Text(text.to_string())
+    } else if !front.is_empty() {
+        NoteData::Card(std::iter::once(front.to_string())
+            .chain(split_sides(back))
+            .collect())
+    } else {
+        return Err(anyhow!("Either `front` or `text` must be set"));
+    };
+    Ok(note)
+}
+
+pub fn write_notes<W: io::Write>(
+    w: W,
+    fmt: &TableFormat,
+    notes: impl Iterator<Item = Result<DbNote>>
+) -> Result<()> {
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(fmt.delimiter())
+        .quote(fmt.quote.unwrap_or(b'"'))
+        .quote_style(match fmt.quote {
+            Some(_) => csv::QuoteStyle::Necessary,
+            None => csv::QuoteStyle::Never,
+        })
+        .from_writer(w);
+    let columns = fmt.columns.as_deref().unwrap_or(&ALL_COLUMNS);
+    if fmt.columns.is_none() {
+        wtr.write_record(columns.iter().map(Column::name))?;
+    }
+
+    for n in notes {
+        let n = n?;
+        let (front, back, text) = match &n.data {
+            NoteData::Text(text) => ("", String::new(), text.as_str()),
+            NoteData::Card(sides) => (
+                sides[0].as_str(),
+                sides[1..].iter()
+                    .map(|side| escape(side))
+                    .collect::<Vec<_>>()
+                    .join(&format!("\n{}\n", SIDE_SEPARATOR)),
+                "",
+            ),
+        };
+        let row: Vec<String> = columns.iter()
+            .map(|col| match col {
+                Column::Uuid => n.uuid.to_string(),
+                Column::Ctime => n.ctime.to_rfc3339(),
+                Column::Tags => n.tags.replace('\n', ","),
+                Column::Front => front.to_string(),
+                Column::Back => back.clone(),
+                Column::Text => text.to_string(),
+            })
+            .collect();
+        wtr.write_record(&row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+// Separator or an escaped separator.
+fn needs_escape(line: &str) -> bool {
+    line.trim_start_matches('\\') == SIDE_SEPARATOR
+}
+
+fn split_sides(back: &str) -> Vec<String> {
+    let mut sides = vec![Vec::new()];
+    for line in back.split('\n') {
+        if line == SIDE_SEPARATOR {
+            sides.push(Vec::new());
+        } else if needs_escape(line) {
+            sides.last_mut().expect("not empty").push(&line[1..]);
+        } else {
+            sides.last_mut().expect("not empty").push(line);
+        }
+    }
+    sides.iter().map(|s| s.join("\n")).collect()
+}
+
+fn escape(side: &str) -> String {
+    side.split('\n')
+        .map(|line| if needs_escape(line) { format!("\\{}", line) } else { line.to_string() })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parse_options() -> Result<()> {
+        let a = args("--format tsv --columns front,back,tags --no-quote rust & !async");
+        let (fmt, rest) = TableFormat::from_args(&a)?;
+        assert_eq!(fmt.format, Format::Tsv);
+        assert_eq!(fmt.columns, Some(vec![Column::Front, Column::Back, Column::Tags]));
+        assert_eq!(fmt.quote, None);
+        assert_eq!(rest, &a[5..]);
+
+        assert!(TableFormat::from_args(&args("--format xml")).is_err());
+        assert!(TableFormat::from_args(&args("--columns front,side")).is_err());
+        assert!(TableFormat::from_args(&args("--quote '")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn read_with_header() -> Result<()> {
+        let fmt = TableFormat { format: Format::Csv, columns: None, quote: Some(b'"') };
+        let notes = read_notes(indoc! {r#"
+            Tags,Front,Back,Text
+            "lang/es,verbs",comer,"to eat
+            ----
+            irregular: no",
+            quotes,,,"Premature optimization"
+        "#}.as_bytes(), &fmt)?;
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].tags, "lang/es,verbs");
+        assert_eq!(notes[0].data, NoteData::Card(vec![
+            "comer".to_string(),
+            "to eat".to_string(),
+            "irregular: no".to_string(),
+        ]));
+        assert_eq!(notes[1].data, NoteData::Text("Premature optimization".to_string()));
+
+        let res = read_notes("front,back\n,empty\n".as_bytes(), &fmt);
+        assert_eq!(res.err().map(|e| e.to_string()), Some("Row 2".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn write_and_read_back() -> Result<()> {
+        let fmt = TableFormat {
+            format: Format::Tsv,
+            columns: Some(vec![Column::Uuid, Column::Ctime, Column::Tags, Column::Front, Column::Back]),
+            quote: Some(b'\''),
+        };
+        let note = InputNote {
+            uuid: None,
+            ctime: None,
+            tags: "a,b/c".to_string(),
+            data: NoteData::Card(vec![
+                "it's".to_string(),
+                "tab\there".to_string(),
+                "3\n-----\n".to_string(),
+                "----\n\\----".to_string(),
+            ]),
+            parent: None,
+        }.to_db_note()?;
+
+        let mut out = Vec::new();
+        write_notes(&mut out, &fmt, std::iter::once(Ok(note.clone())))?;
+        let notes = read_notes(out.as_slice(), &fmt)?;
+        assert_eq!(notes.len(), 1);
+        let read = notes[0].to_db_note()?;
+        assert_eq!(read.uuid, note.uuid);
+        assert_eq!(read.ctime, note.ctime);
+        assert_eq!(read.tags, note.tags);
+        assert_eq!(read.data, note.data);
+        Ok(())
+    }
+}
@@ -0,0 +1,393 @@
+// Data exchanged with the web app (see `pushToServer` and `pullFromServer`
+// in src/db.js).
+//
+// The web app has its own note ids: a timestamp with a random suffix.
+// Notes created by the web app get a UUID derived from that id, and the id is
+// kept in `webapp_notes` to send it back. Notes created by the CLI are sent
+// with their UUID as id.
+//
+// The web app bumps `ver` on every edit. We send the hash of the current
+// version as `ver`, so a note that comes back with a hash of any of its
+// versions was not edited in the app.
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use anyhow::Result;
+use chrono::{DateTime, TimeZone, Utc};
+use regex::Regex;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::db;
+use crate::note::{DbNote, NoteData};
+
+// Namespace for UUIDs of notes created by the web app.
+const WEBAPP_NAMESPACE: Uuid = Uuid::from_u128(0x2b1f_6c4e_93d5_4a7e_b0c1_8e52_7f3a_d916);
+
+// Tag for notes created in the web app.
+pub const WEBAPP_TAG: &str = "webapp";
+
+// The web app splits question and answer on a line of dashes.
+const CARD_SEPARATOR: &str = "\n----\n";
+
+#[derive(Serialize, Deserialize)]
+pub struct WebData {
+    pub notes: Vec<WebNote>,
+    pub reviews: Vec<WebReview>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebNote {
+    pub id: String,
+    pub text: String,
+    pub ver: String,
+    #[serde(with = "js_time")]
+    pub last_review: DateTime<Utc>,
+    #[serde(with = "js_time")]
+    pub next_review: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WebReview {
+    // Id of the note in the web app.
+    pub note: String,
+    #[serde(with = "js_time")]
+    pub time: DateTime<Utc>,
+    pub result: String,
+}
+
+// Timestamps are formatted as by `Date.toISOString()`, the web app compares
+// them as strings.
+mod js_time {
+    use chrono::{DateTime, SecondsFormat, Utc};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(t: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&t.to_rfc3339_opts(SecondsFormat::Millis, true))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<DateTime<Utc>, D::Error> {
+        DateTime::<Utc>::deserialize(d)
+    }
+}
+
+#[derive(Default)]
+pub struct ImportStats {
+    pub notes: usize,
+    pub reviews: usize,
+    // Number of skipped reviews by unknown result.
+    pub skipped: BTreeMap<String, usize>,
+}
+
+// Merges notes and reviews from the web app into the DB.
+// This is idempotent, so the app can push the same data again and again.
+// New notes are tagged with `tags` (\n delimited).
+pub fn import(db: &Connection, data: &WebData, tags: &str) -> Result<ImportStats> {
+    let tx = db.unchecked_transaction()?;
+    let mut stats = ImportStats::default();
+    for n in data.notes.iter() {
+        if import_note(&tx, n, tags)? {
+            stats.notes += 1;
+        }
+    }
+    // Reviews with unknown results are skipped, otherwise the app could
+    // never sync again.
+    let results = db::review_results(&tx)?;
+    for r in data.reviews.iter() {
+        if !results.contains(&r.result) {
+            *stats.skipped.entry(r.result.clone()).or_default() += 1;
+        } else if import_review(&tx, r)? {
+            stats.reviews += 1;
+        }
+    }
+    // Reviews are merged first to know if the app schedule is up to date.
+    for n in data.notes.iter() {
+        import_schedule(&tx, n)?;
+    }
+    tx.commit()?;
+    Ok(stats)
+}
+
+// Current notes and all the reviews in the format of the web app.
+pub fn export(db: &Connection) -> Result<WebData> {
+    let mut notes = Vec::new();
+    for note in db::active_notes(db)?.iter()? {
+        let note = note?;
+        let entry = db::queue_entry(db, &note.uuid)?;
+        let (hash, _) = note.hash_and_json();
+        notes.push(WebNote {
+            id: web_id(db, &note.uuid)?,
+            text: data_to_text(&note.data),
+            ver: hash,
+            last_review: entry.last_review,
+            next_review: entry.next_review,
+        });
+    }
+
+    // Original time of reviews made in the app is kept in the decision,
+    // the app uses it as a key.
+    let mut q = db.prepare("
+        select
+            coalesce(w.id, r.note_id),
+            coalesce(json_extract(r.decision, '$.time'), r.ctime),
+            rr.label
+        from review r
+        join review_result rr on rr.id = r.result
+        left join webapp_notes w on w.uuid = r.note_id
+        order by r.ctime asc, r.id asc
+    ")?;
+    let mut rows = q.query([])?;
+    let mut reviews = Vec::new();
+    while let Some(row) = rows.next()? {
+        reviews.push(WebReview {
+            note: row.get(0)?,
+            time: DateTime::parse_from_rfc3339(&row.get::<_, String>(1)?)?
+                .with_timezone(&Utc),
+            result: row.get(2)?,
+        });
+    }
+    Ok(WebData { notes, reviews })
+}
+
+// Returns true if a new version of the note was saved.
+fn import_note(db: &Connection, n: &WebNote, tags: &str) -> Result<bool> {
+    let uuid = note_uuid(db, &n.id)?;
+    let known_ver: bool = db.query_row("
+        select
+            exists (select 1 from notes where uuid = ?1 and hash = ?2)
+            or exists (select 1 from webapp_notes where id = ?3 and ver = ?2)
+        ",
+        params![uuid.to_string(), n.ver, n.id],
+        |row| row.get(0))?;
+    if known_ver {
+        return Ok(false);
+    }
+
+    let current = db::current_note(db, &uuid)?;
+    let note = DbNote {
+        uuid,
+        ctime: current.as_ref()
+            .map(|c| c.ctime)
+            .or_else(|| id_time(&n.id))
+            .unwrap_or(n.last_review),
+        tags: current.as_ref()
+            .map_or_else(|| tags.to_string(), |c| c.tags.clone()),
+        data: text_to_data(&n.text, current.as_ref().map(|c| &c.data)),
+        parent: None,
+    };
+    db::insert_note(db, &note)?;
+    db.execute("
+        insert into webapp_notes
+          (id, uuid, ver)
+        values
+          (?, ?, ?)
+        on conflict (id) do update set ver = excluded.ver
+        ",
+        params![n.id, uuid.to_string(), n.ver])?;
+    let hash = |n: &DbNote| n.hash_and_json().0;
+    Ok(current.as_ref().map(hash) != Some(hash(&note)))
+}
+
+// Returns true if the review was not known yet.
+fn import_review(db: &Connection, r: &WebReview) -> Result<bool> {
+    let uuid = note_uuid(db, &r.note)?;
+    if db::current_note(db, &uuid)?.is_none() {
+        // The note was deleted on our side.
+        return Ok(false);
+    }
+    let decision = serde_json::json!({
+        "source": "webapp",
+        "time": r.time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+    });
+    db::insert_review(db, &uuid, &r.time, &r.result, &decision)
+}
+
+// The app schedule is used only if the app has seen the latest review.
+// Otherwise the note was reviewed elsewhere since the last sync.
+fn import_schedule(db: &Connection, n: &WebNote) -> Result<()> {
+    let uuid = note_uuid(db, &n.id)?;
+    if db::current_note(db, &uuid)?.is_none() {
+        return Ok(());
+    }
+    let up_to_date = match db::review_history(db, &uuid)?.last() {
+        Some(last) => n.last_review >= last.ctime,
+        None => true,
+    };
+    if up_to_date {
+        db::set_next_review(db, &uuid, &n.next_review)?;
+    }
+    Ok(())
+}
+
+fn note_uuid(db: &Connection, id: &str) -> Result<Uuid> {
+    let uuid: Option<String> = db.query_row(
+        "select uuid from webapp_notes where id = ?",
+        [id],
+        |row| row.get(0)).optional()?;
+    match uuid {
+        Some(uuid) => Ok(Uuid::parse_str(&uuid)?),
+        None => Ok(Uuid::parse_str(id)
+            .unwrap_or_else(|_| Uuid::new_v5(&WEBAPP_NAMESPACE, id.as_bytes()))),
+    }
+}
+
+fn web_id(db: &Connection, uuid: &Uuid) -> Result<String> {
+    let id: Option<String> = db.query_row(
+        "select id from webapp_notes where uuid = ?",
+        [uuid.to_string()],
+        |row| row.get(0)).optional()?;
+    Ok(id.unwrap_or_else(|| uuid.to_string()))
+}
+
+// Ids are generated as `Date.now().toString(36)` with a random suffix
+// starting with a dot.
+fn id_time(id: &str) -> Option<DateTime<Utc>> {
+    let (ts, _) = id.split_once('.')?;
+    let ms = i64::from_str_radix(ts, 36).ok()?;
+    Utc.timestamp_millis_opt(ms).single()
+}
+
+fn data_to_text(data: &NoteData) -> String {
+    match data {
+        NoteData::Text(text) => text.clone(),
+        NoteData::Card(sides) => sides.join(CARD_SEPARATOR),
+    }
+}
+
+// Cards edited in the app stay cards while they have several sides.
+fn text_to_data(text: &str, current: Option<&NoteData>) -> NoteData {
+    if let Some(NoteData::Card(_)) = current {
+        static SEPARATOR: OnceLock<Regex> = OnceLock::new();
+        let sep = SEPARATOR.get_or_init(|| Regex::new(r"\n-{4,}\n").expect("valid regex"));
+        let sides: Vec<String> = sep.split(text).map(String::from).collect();
+        if sides.len() > 1 {
+            return NoteData::Card(sides);
+        }
+    }
+    NoteData::Text(text.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn test_db() -> Result<Connection> {
+        let db = db::open(":memory:")?;
+        db::init_schema(&db)?;
+        Ok(db)
+    }
+
+    fn web_note(id: &str, text: &str, ver: &str) -> WebNote {
+        let now = Utc::now();
+        WebNote {
+            id: id.to_string(),
+            text: text.to_string(),
+            ver: ver.to_string(),
+            last_review: now,
+            next_review: now,
+        }
+    }
+
+    #[test]
+    fn import_is_idempotent() -> Result<()> {
+        let db = test_db()?;
+        let time = Utc.timestamp_millis_opt(1_700_000_000_123).unwrap();
+        let data = WebData {
+            notes: vec![web_note("lp2d5a1c.x1", "hello", "lp2d5a1c")],
+            reviews: vec![WebReview {
+                note: "lp2d5a1c.x1".to_string(),
+                time,
+                result: "easy".to_string(),
+            }],
+        };
+        let stats = import(&db, &data, "webapp")?;
+        assert_eq!((stats.notes, stats.reviews), (1, 1));
+        let stats = import(&db, &data, "webapp")?;
+        assert_eq!((stats.notes, stats.reviews), (0, 0));
+
+        let note = db::active_notes(&db)?.iter()?.next().unwrap()?;
+        assert_eq!(note.tags, "webapp");
+        assert_eq!(Some(note.ctime), id_time("lp2d5a1c.x1"));
+        assert_eq!(note.uuid, note_uuid(&db, "lp2d5a1c.x1")?);
+
+        let out = export(&db)?;
+        assert_eq!(out.notes[0].id, "lp2d5a1c.x1");
+        assert_eq!(out.notes[0].ver, note.hash_and_json().0);
+        assert_eq!(out.reviews[0].note, "lp2d5a1c.x1");
+        assert_eq!(out.reviews[0].time, time);
+
+        // Unknown results don't fail the import.
+        let bad = WebData {
+            notes: vec![],
+            reviews: vec![WebReview {
+                note: "lp2d5a1c.x1".to_string(),
+                time: Utc::now(),
+                result: "meh".to_string(),
+            }],
+        };
+        let stats = import(&db, &bad, "webapp")?;
+        assert_eq!(stats.skipped.get("meh"), Some(&1));
+
+        // The app gets hash as `ver` after pulling the data.
+        let stats = import(&db, &out, "webapp")?;
+        assert_eq!((stats.notes, stats.reviews), (0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn stale_app_version_is_ignored() -> Result<()> {
+        let db = test_db()?;
+        let note = DbNote {
+            uuid: Uuid::new_v4(),
+            ctime: Utc::now(),
+            tags: "math".to_string(),
+            data: NoteData::Card(vec!["2+2".to_string(), "4".to_string()]),
+            parent: None,
+        };
+        db::insert_notes(&db, std::slice::from_ref(&note))?;
+        let pulled = export(&db)?;
+        assert_eq!(pulled.notes[0].text, "2+2\n----\n4");
+
+        // The note is updated in the CLI after the app pulled it.
+        let updated = DbNote {
+            data: NoteData::Card(vec!["2*2".to_string(), "4".to_string()]),
+            ..note.clone()
+        };
+        db::insert_notes(&db, std::slice::from_ref(&updated))?;
+        assert_eq!(import(&db, &pulled, "webapp")?.notes, 0);
+        assert_eq!(db::current_note(&db, &note.uuid)?, Some(updated));
+
+        // The note is edited in the app.
+        let mut edited = pulled;
+        edited.notes[0].text = "2+3\n-----\n5".to_string();
+        edited.notes[0].ver = "lp2d5a1c".to_string();
+        assert_eq!(import(&db, &edited, "webapp")?.notes, 1);
+        let current = db::current_note(&db, &note.uuid)?.unwrap();
+        assert_eq!(current.data, NoteData::Card(vec!["2+3".to_string(), "5".to_string()]));
+        assert_eq!(current.tags, "math");
+        Ok(())
+    }
+
+    #[test]
+    fn schedule_from_app_if_up_to_date() -> Result<()> {
+        let db = test_db()?;
+        let mut n = web_note("lp2d5a1c.x1", "hello", "lp2d5a1c");
+        n.next_review = n.last_review + Duration::days(3);
+        let data = WebData { notes: vec![n], reviews: vec![] };
+        import(&db, &data, "")?;
+        let uuid = note_uuid(&db, "lp2d5a1c.x1")?;
+        let entry = db::queue_entry(&db, &uuid)?;
+        assert_eq!(db::to_db_time(&entry.next_review), db::to_db_time(&data.notes[0].next_review));
+
+        // Reviewed in the CLI after the last sync.
+        let later = Utc::now() + Duration::hours(1);
+        let next = later + Duration::days(10);
+        db::save_review(&db, &uuid, &later, "good", &serde_json::json!({}), &next)?;
+        import(&db, &data, "")?;
+        let entry = db::queue_entry(&db, &uuid)?;
+        assert_eq!(db::to_db_time(&entry.next_review), db::to_db_time(&next));
+        Ok(())
+    }
+}